@@ -0,0 +1,34 @@
+// ボイドモデルのupdateにかかる時間を測る
+// cargo bench --bench boid
+// 以前のコミットと比べるときは sh benches/compare.sh (このファイルを以前のコミットでも動かす)
+use std::time::Instant;
+
+use rust_webpack_template::boid::boid::{Boid, BoidParameters};
+use rust_webpack_template::engine::Game;
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+const WARMUP_STEPS: usize = 5;
+const STEPS: usize = 30;
+const DT: f64 = 1.0 / 60.0;
+
+fn main() {
+    for count in [1_000, 5_000, 20_000] {
+        let parameters = BoidParameters {
+            boid_count: 0,
+            ..BoidParameters::default()
+        };
+        let mut game = Boid::with_parameters(WIDTH, HEIGHT, parameters);
+        game.update_boid_count(count);
+
+        for _ in 0..WARMUP_STEPS {
+            game.update(DT);
+        }
+        let start = Instant::now();
+        for _ in 0..STEPS {
+            game.update(DT);
+        }
+        let per_step = start.elapsed().as_secs_f64() * 1000.0 / STEPS as f64;
+        println!("boid update, {:>6} agents: {:>9.3} ms / step", count, per_step);
+    }
+}
@@ -0,0 +1,21 @@
+#!/bin/sh
+# 今のツリーと以前のコミットで同じベンチマーク (benches/boid.rs) を動かして比べる
+# sh benches/compare.sh [rev]
+# revの既定はボイドの位置と速度をf32の列にする前のコミット
+set -e
+rev=${1:-0e93a66}
+root=$(git rev-parse --show-toplevel)
+dir=$(mktemp -d)
+trap 'git -C "$root" worktree remove --force "$dir"' EXIT
+git -C "$root" worktree add --quiet --detach "$dir" "$rev"
+cp "$root/benches/boid.rs" "$dir/benches/boid.rs"
+# Cargo.lockはコミットしていないので、同じ版の依存で作る
+[ -f "$root/Cargo.lock" ] && cp "$root/Cargo.lock" "$dir/Cargo.lock"
+(cd "$dir" && CARGO_TARGET_DIR="$root/target/compare" cargo bench --quiet --bench boid) > "$dir/previous.txt"
+(cd "$root" && cargo bench --quiet --bench boid) > "$dir/current.txt"
+# 同じエージェント数の行を並べる
+paste -d '\t' "$dir/previous.txt" "$dir/current.txt" | awk -F '\t' -v rev="$rev" '{
+    split($1, a, ":"); split($2, b, ":");
+    split(a[2], p, " "); split(b[2], c, " ");
+    printf "%s: %s %9.3f ms, current %9.3f ms / step (%.2fx)\n", a[1], rev, p[1], c[1], p[1] / c[1]
+}'
@@ -18,6 +18,10 @@ function navigate(path) {
 
 function render(path) {
     console.log(path);
+    // クエリ文字列はシミュレーションの設定に使う
+    const url = new URL(path, window.location.origin);
+    path = url.pathname;
+    const params = url.searchParams;
     document.body.classList.remove("body-locked");
 
     // wasmの関数を呼び出す
@@ -39,11 +43,54 @@ function render(path) {
         }
         if (path === "/boid" || path === "/boid/") {
             reset();
-            module.boid_start();
+            const seed = seedFrom(params);
+            replaceQuery("seed", seed);
+            module.boid_start(seed);
+        }
+        if (path === "/boid3d" || path === "/boid3d/") {
+            reset();
+            const seed = seedFrom(params);
+            replaceQuery("seed", seed);
+            module.boid3d_start(seed);
+        }
+        if (path === "/life" || path === "/life/") {
+            reset();
+            const seed = seedFrom(params);
+            replaceQuery("seed", seed);
+            const rule = params.get("rule") || "23/3/2";
+            const view = params.get("view") || "states";
+            showLifeSettings(rule, view);
+            module.life_start(rule, view, seed);
         }
     }).catch(console.error);
 }
 
+// シードはURLに残しておき、同じシミュレーションを共有・再現できるようにする
+// 指定が無ければランダムに決める (パネルのあるページではほかのパラメータもwasm側でURLに書く)
+function seedFrom(params) {
+    const seed = Number.parseInt(params.get("seed"), 10);
+    if (Number.isInteger(seed) && seed >= 0) {
+        return seed % 4294967296;
+    }
+    return Math.floor(Math.random() * 4294967296);
+}
+
+// ライフゲームの設定フォームに今のルールと表示を入れておく
+function showLifeSettings(rule, view) {
+    const ruleInput = document.getElementById("life-rule");
+    const viewSelect = document.getElementById("life-view");
+    if (ruleInput) ruleInput.value = rule;
+    if (viewSelect) viewSelect.value = view;
+}
+
+// 履歴を増やさずにURLのクエリを書き換える
+function replaceQuery(name, value) {
+    const url = new URL(window.location.href);
+    url.searchParams.set(name, value);
+    const path = url.pathname + url.search;
+    window.history.replaceState({ path }, "", path);
+}
+
 async function render_md(path) {
     const res = await fetch(path);
     const markdown = await res.text();
@@ -100,7 +147,7 @@ function Initialize(){
         });
 
         // Initialize on page load
-        navigate(window.location.pathname);
+        navigate(window.location.pathname + window.location.search);
 
     // spotlight effect
     document.addEventListener("mousemove", (e) => {
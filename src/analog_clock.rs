@@ -36,7 +36,7 @@ impl Game for AnalogClock {
         Ok(Box::new(AnalogClock::new(self.width, self.height)))
     }
 
-    fn update(&mut self) {
+    fn update(&mut self, _dt: f64) {
         let now = js_sys::Date::new_0();
         self.second = now.get_seconds() as f32;
         self.minute = now.get_minutes() as f32 + self.second / 60.0;
@@ -48,7 +48,7 @@ impl Game for AnalogClock {
     fn draw(&self, renderer: &Renderer2d) {
         renderer.clear();
         let center = Point::new_fron_uint(self.width / 2, self.height / 2);
-        let radius = (self.width.min(self.height) / 2 - 20 ) as f64;
+        let radius = (self.width.min(self.height) / 2).saturating_sub(20) as f64;
 
         // Draw clock face
         renderer.circle(center, radius, "black");
@@ -0,0 +1,231 @@
+// 群れの状態を表す指標 (パラメータによる振る舞いの違いを調べる用)
+use std::collections::VecDeque;
+
+use crate::engine::{Point, Renderer2d};
+use super::boid::offset;
+use super::grid::SpatialGrid;
+
+pub const SAMPLE_INTERVAL: f64 = 0.1; // 指標を記録する間隔 (秒)
+const HISTORY_SIZE: usize = 3600; // 書き出し用に残す数 (6分)
+const CHART_SIZE: usize = 300;    // グラフに描く数 (30秒)
+const CHART_WIDTH: f64 = 240.0;
+const CHART_HEIGHT: f64 = 120.0;
+const CHART_MARGIN: f64 = 10.0;
+const POLARIZATION_COLOR: &str = "rgba(0, 255, 0, 0.9)";
+const MILLING_COLOR: &str = "rgba(0, 160, 255, 0.9)";
+const NEAREST_COLOR: &str = "rgba(255, 220, 0, 0.9)";
+const CLUSTERS_COLOR: &str = "rgba(255, 80, 80, 0.9)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FlockMetrics {
+    pub polarization: f64,          // 向きの揃い具合 (0: ばらばら, 1: 全員同じ向き)
+    pub milling: f64,               // 重心のまわりを回っている度合い (角運動量の秩序変数 0..1)
+    pub mean_nearest_neighbor: f64, // 一番近い仲間までの距離の平均 (2体未満なら0)
+    pub clusters: usize,            // cluster_distance以内でつながっているかたまりの数
+}
+
+impl FlockMetrics {
+    // torusが(幅, 高さ)のときは端がつながっているとして距離を測る
+    // 重心は端をまたいだ群れだと意味がないので、millingは画面上の位置のまま計算する
+    pub fn measure(
+        positions: &[Point],
+        velocities: &[Point],
+        torus: Option<(f64, f64)>,
+        cluster_distance: f64,
+        grid: &SpatialGrid,
+    ) -> Self {
+        let n = positions.len();
+        if n == 0 {
+            return FlockMetrics::default();
+        }
+        let torus = torus.map(|(width, height)| Point::new(width, height));
+
+        // 向きの単位ベクトルの平均の長さ
+        let headings: Vec<Point> = velocities.iter().map(|v| unit(*v)).collect();
+        let sum = headings.iter().fold(Point::new(0.0, 0.0), |sum, h| sum + *h);
+        let polarization = length(sum) / n as f64;
+
+        // 重心から見た向き × 進行方向 (外積) の平均の大きさ
+        let center = positions.iter().fold(Point::new(0.0, 0.0), |sum, p| sum + *p) * (1.0 / n as f64);
+        let angular: f64 = positions
+            .iter()
+            .zip(&headings)
+            .map(|(p, h)| {
+                let r = unit(Point::new(p.x - center.x, p.y - center.y));
+                r.x * h.y - r.y * h.x
+            })
+            .sum();
+        let milling = angular.abs() / n as f64;
+
+        // かたまりと最近傍の距離
+        // 最近傍がcluster_distanceの中に見つからないときは、探す範囲を倍々に広げる
+        let cluster_sq = cluster_distance.powi(2);
+        let mut components = UnionFind::new(n);
+        let mut nearest_sum = 0.0;
+        for i in 0..n {
+            let mut nearest = f64::INFINITY;
+            let mut radius = cluster_distance.max(1.0);
+            // かたまりは最初の範囲 (cluster_distance以上) で探した分だけでつなぐ
+            let mut first = true;
+            loop {
+                for j in grid.neighbors(positions[i], radius) {
+                    if i == j {
+                        continue;
+                    }
+                    let d = offset(positions[i], positions[j], torus);
+                    let distance = d.x * d.x + d.y * d.y;
+                    nearest = nearest.min(distance);
+                    if first && distance < cluster_sq {
+                        components.union(i, j);
+                    }
+                }
+                // 範囲内は全部調べているので、範囲内に見つかればそれが最近傍
+                if n < 2 || nearest <= radius * radius {
+                    break;
+                }
+                radius *= 2.0;
+                first = false;
+            }
+            if nearest.is_finite() {
+                nearest_sum += nearest.sqrt();
+            }
+        }
+        let mean_nearest_neighbor = if n >= 2 { nearest_sum / n as f64 } else { 0.0 };
+
+        FlockMetrics {
+            polarization,
+            milling,
+            mean_nearest_neighbor,
+            clusters: components.count(),
+        }
+    }
+}
+
+// 時刻つきの指標の履歴
+#[derive(Debug, Clone, Default)]
+pub struct MetricsHistory {
+    samples: VecDeque<(f64, FlockMetrics)>,
+}
+
+impl MetricsHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, time: f64, metrics: FlockMetrics) {
+        if self.samples.len() == HISTORY_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((time, metrics));
+    }
+
+    pub fn latest(&self) -> Option<&FlockMetrics> {
+        self.samples.back().map(|(_, metrics)| metrics)
+    }
+
+    pub fn samples(&self) -> impl Iterator<Item = &(f64, FlockMetrics)> {
+        self.samples.iter()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("time,polarization,milling,mean_nearest_neighbor,clusters\n");
+        for (time, m) in &self.samples {
+            csv.push_str(&format!(
+                "{:.4},{:.6},{:.6},{:.4},{}\n",
+                time, m.polarization, m.milling, m.mean_nearest_neighbor, m.clusters
+            ));
+        }
+        csv
+    }
+
+    // 画面左下に直近の指標のグラフを描く
+    // polarizationとmillingは0..1、最近傍距離とかたまりの数は表示中の最大値で割って描く
+    pub fn draw_chart(&self, renderer: &Renderer2d, screen_height: f64) {
+        let top_left = Point::new(CHART_MARGIN, screen_height - CHART_HEIGHT - CHART_MARGIN);
+        renderer.rect(top_left, CHART_WIDTH, CHART_HEIGHT, "rgba(0, 0, 0, 0.6)");
+        let recent: Vec<&FlockMetrics> = self.samples.iter().skip(self.samples.len().saturating_sub(CHART_SIZE)).map(|(_, m)| m).collect();
+        let Some(latest) = recent.last() else {
+            return;
+        };
+
+        let max_nearest = recent.iter().map(|m| m.mean_nearest_neighbor).fold(0.0, f64::max).max(f64::EPSILON);
+        let max_clusters = recent.iter().map(|m| m.clusters).max().unwrap_or(1).max(1) as f64;
+        let graph_top = top_left.y + 20.0;
+        let graph_height = CHART_HEIGHT - 24.0;
+        let values = |m: &FlockMetrics| {
+            [m.polarization, m.milling, m.mean_nearest_neighbor / max_nearest, m.clusters as f64 / max_clusters]
+        };
+        let step = CHART_WIDTH / (CHART_SIZE - 1) as f64;
+        let colors = [POLARIZATION_COLOR, MILLING_COLOR, NEAREST_COLOR, CLUSTERS_COLOR];
+        for (k, color) in colors.iter().enumerate() {
+            let points: Vec<Point> = recent
+                .iter()
+                .enumerate()
+                .map(|(i, m)| Point::new(top_left.x + i as f64 * step, graph_top + graph_height * (1.0 - values(m)[k].clamp(0.0, 1.0))))
+                .collect();
+            for (a, b) in points.iter().zip(points.iter().skip(1)) {
+                renderer.line(*a, *b, 1.0, color);
+            }
+        }
+
+        let labels = [
+            (POLARIZATION_COLOR, format!("P {:.2}", latest.polarization)),
+            (MILLING_COLOR, format!("M {:.2}", latest.milling)),
+            (NEAREST_COLOR, format!("NN {:.1}", latest.mean_nearest_neighbor)),
+            (CLUSTERS_COLOR, format!("C {}", latest.clusters)),
+        ];
+        for (i, (color, label)) in labels.iter().enumerate() {
+            renderer.text(Point::new(top_left.x + 4.0 + i as f64 * 60.0, top_left.y + 4.0), label, 11.0, color);
+        }
+    }
+}
+
+// かたまりを数えるための素集合
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        Self { parent: (0..n).collect() }
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        let mut root = i;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        // 経路圧縮
+        let mut i = i;
+        while self.parent[i] != root {
+            let next = self.parent[i];
+            self.parent[i] = root;
+            i = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[a] = b;
+        }
+    }
+
+    fn count(&mut self) -> usize {
+        (0..self.parent.len()).filter(|&i| self.find(i) == i).count()
+    }
+}
+
+fn length(v: Point) -> f64 {
+    (v.x * v.x + v.y * v.y).sqrt()
+}
+
+fn unit(v: Point) -> Point {
+    let norm = length(v);
+    if norm == 0.0 {
+        Point::new(0.0, 0.0)
+    } else {
+        v * (1.0 / norm)
+    }
+}
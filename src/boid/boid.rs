@@ -1,25 +1,214 @@
 // ボイドモデルシミュレーター
 use anyhow::{anyhow, Result};
-use std::{f64::consts::PI};
-use rand::Rng;
+use std::{collections::VecDeque, f64::consts::PI, sync::LazyLock};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use async_trait::async_trait;
 
 use crate::engine::{
-    self, Game, Point, Renderer2d
+    self, Game, Point, PointerEvent, PointerKind, Renderer2d, Vector
 };
+use crate::parameter::{self, Parameter, ParameterChange, ParameterSpec, ParameterValue, Preset};
+use super::analytics::{FlockMetrics, MetricsHistory, SAMPLE_INTERVAL};
+use super::flow::FlowField;
+use super::grid::SpatialGrid;
+use super::kinematics::{Kinematics, NeighborColumns, NeighborRules};
+use super::obstacle::Obstacle;
+use super::trajectory::{AgentState, Frame, Trajectory};
+
+// 速度はpx/s、加速度はpx/s²で持つ
+// 操舵の重みとmax_forceはこのフレームレートの1フレームあたりの量 (もともと1フレーム1回の更新で決めた値なので)
+pub const REFERENCE_FPS: f64 = 60.0;
+pub const FORCE_UNIT: f64 = REFERENCE_FPS * REFERENCE_FPS; // 重み1の加速度 (px/s²)
+
+const POLYGON_CLOSE_DISTANCE: f64 = 10.0; // 最初の頂点のこの距離内をクリックすると多角形を閉じる
+const PREY_RGB: (u8, u8, u8) = (0, 255, 0);
+const PREDATOR_RGB: (u8, u8, u8) = (255, 80, 0);
+const DENSITY_SCALE: f64 = 15.0; // 近傍がこの数以上いたら一番濃い色にする
+const TRAIL_ALPHA: f64 = 0.5;    // 軌跡の一番新しいところの不透明度
+const WAYPOINT_COLOR: &str = "rgba(255, 200, 0, 0.3)";
+const ATTRACTOR_COLOR: &str = "rgba(0, 128, 255, 0.15)";
+const REPULSOR_COLOR: &str = "rgba(255, 0, 0, 0.15)";
 
 // ボイドモデルシミュレータ
 pub struct Boid {
-    agents: Vec<BoidAgent>,
+    agents: Vec<BoidAgent>,  // 種や軌跡など (kinematicsと同じ並び)
+    next_id: usize,          // 次に生まれるエージェントの番号
+    kinematics: Kinematics, // 位置・速度・加速度
     width: u32,  // 画面の幅
     height: u32, // 画面の高さ
     parameters: BoidParameters,
+    grid: SpatialGrid, // 近傍探索用 update毎に作り直す
+    columns: NeighborColumns, // gridのセルの順に並べ直した位置・速度 (flockで使う)
+    speed_limits: Vec<f32>, // flockで書くエージェントごとの最高速 (積分で使う)
+    previous: Vec<Point>,   // 積分する前の位置 (障害物との衝突判定用)
+    obstacles: Vec<Obstacle>,
+    pending_polygon: Vec<Point>, // Ctrl+クリックで追加中の多角形の頂点
+    waypoints: Vec<Point>,       // 被食者が順に向かう目標 (最後まで行ったら最初に戻る)
+    flow: FlowField,             // parameters.seedで作る
+    attractor: Option<Attractor>, // ボタンを押している間だけある
+    rng: StdRng, // parameters.seedで初期化する 同じシードなら同じ動きになる
+    time: f64,   // シミュレーション内の経過時間 (秒)
+    next_sample: f64,
+    metrics: MetricsHistory,
+    metrics_grid: SpatialGrid, // 指標の計算用 (被食者だけを入れる)
+    trajectory: Option<Trajectory>, // 記録中か、最後に記録したもの
+    recording: bool,
+    next_record: f64,
+}
+
+// パネルに出すパラメータ (既定値はBoidParameters::default())
+static PARAMETERS: LazyLock<Vec<Parameter<Boid>>> = LazyLock::new(|| {
+    let d = BoidParameters::default();
+    let count: Vec<Parameter<Boid>> = vec![
+        Parameter::integer("boidcount", "Number of Boid", (1.0, 1000.0), d.boid_count as f64,
+            |b| b.parameters.boid_count as u64, |b, v| b.update_boid_count(v as usize)),
+    ];
+    // 群れ以外の振る舞い・ポインタの操作とシード
+    let behavior: Vec<Parameter<Boid>> = vec![
+        Parameter::number("viewangle", "View Angle", (0.0, 360.0, 1.0), d.view_angle,
+            |b| b.parameters.view_angle, |b, v| b.parameters.view_angle = v),
+        Parameter::toggle("showperception", "Show Perception", d.show_perception,
+            |b| b.parameters.show_perception, |b, v| b.parameters.show_perception = v),
+        Parameter::number("avoidforce", "Avoidance Force", (0.0, 3.0, 0.05), d.avoidance_force,
+            |b| b.parameters.avoidance_force, |b, v| b.parameters.avoidance_force = v),
+        Parameter::number("lookahead", "Look Ahead", (0.0, 200.0, 1.0), d.look_ahead,
+            |b| b.parameters.look_ahead, |b, v| b.parameters.look_ahead = v),
+        Parameter::number("obstacleradius", "Obstacle Radius", (5.0, 150.0, 1.0), d.obstacle_radius,
+            |b| b.parameters.obstacle_radius, |b, v| b.parameters.obstacle_radius = v),
+        Parameter::integer("predatorcount", "Number of Predator", (0.0, 20.0), d.predator_count as f64,
+            |b| b.parameters.predator_count as u64, |b, v| b.update_predator_count(v as usize)),
+        Parameter::number("panicdistance", "Panic Distance", (0.0, 300.0, 1.0), d.panic_distance,
+            |b| b.parameters.panic_distance, |b, v| b.parameters.panic_distance = v),
+        Parameter::toggle("respawnprey", "Respawn Prey", d.respawn_prey,
+            |b| b.parameters.respawn_prey, |b, v| b.parameters.respawn_prey = v),
+        Parameter::choice("pointertool", "Pointer Tool", PointerTool::NAMES, d.pointer_tool as usize,
+            |b| b.parameters.pointer_tool as usize, |b, i| b.update_pointer_tool(PointerTool::from_value(i as f64))),
+        Parameter::number("attractradius", "Attractor Radius", (10.0, 500.0, 1.0), d.attractor_radius,
+            |b| b.parameters.attractor_radius, |b, v| b.parameters.attractor_radius = v),
+        Parameter::number("attractstrength", "Attractor Strength", (0.0, 2.0, 0.05), d.attractor_strength,
+            |b| b.parameters.attractor_strength, |b, v| b.parameters.attractor_strength = v),
+        Parameter::number("windforce", "Wind Force", (0.0, 1.0, 0.01), d.wind_force,
+            |b| b.parameters.wind_force, |b, v| b.parameters.wind_force = v),
+        Parameter::number("winddirection", "Wind Direction (deg)", (0.0, 360.0, 1.0), d.wind_direction,
+            |b| b.parameters.wind_direction, |b, v| b.parameters.wind_direction = v),
+        Parameter::number("flowforce", "Flow Force", (0.0, 1.0, 0.01), d.flow_force,
+            |b| b.parameters.flow_force, |b, v| b.parameters.flow_force = v),
+        Parameter::number("flowscale", "Flow Scale", (20.0, 1000.0, 10.0), d.flow_scale,
+            |b| b.parameters.flow_scale, |b, v| b.parameters.flow_scale = v),
+        Parameter::number("flowspeed", "Flow Speed", (0.0, 1.0, 0.01), d.flow_speed,
+            |b| b.parameters.flow_speed, |b, v| b.parameters.flow_speed = v),
+        Parameter::toggle("showflow", "Show Flow Field", d.show_flow,
+            |b| b.parameters.show_flow, |b, v| b.parameters.show_flow = v),
+        Parameter::number("goalforce", "Goal Force", (0.0, 1.0, 0.01), d.goal_force,
+            |b| b.parameters.goal_force, |b, v| b.parameters.goal_force = v),
+        Parameter::number("waypointradius", "Waypoint Radius", (5.0, 200.0, 1.0), d.waypoint_radius,
+            |b| b.parameters.waypoint_radius, |b, v| b.parameters.waypoint_radius = v),
+        Parameter::integer("seed", "Seed", (0.0, 4294967295.0), d.seed as f64,
+            |b| b.parameters.seed, |b, v| b.update_seed(v)),
+    ];
+    // 表示と記録
+    let display: Vec<Parameter<Boid>> = vec![
+        Parameter::toggle("showmetrics", "Show Metrics", d.show_metrics,
+            |b| b.parameters.show_metrics, |b, v| b.parameters.show_metrics = v),
+        Parameter::number("clusterdistance", "Cluster Distance", (5.0, 200.0, 1.0), d.cluster_distance,
+            |b| b.parameters.cluster_distance, |b, v| b.parameters.cluster_distance = v),
+        Parameter::choice("colormode", "Color", ColorMode::NAMES, d.color_mode as usize,
+            |b| b.parameters.color_mode as usize, |b, i| b.parameters.color_mode = ColorMode::from_value(i as f64)),
+        Parameter::toggle("trails", "Trails", d.trails,
+            |b| b.parameters.trails, |b, v| b.parameters.trails = v),
+        Parameter::integer("traillength", "Trail Length", (2.0, 120.0), d.trail_length as f64,
+            |b| b.parameters.trail_length as u64, |b, v| b.parameters.trail_length = v as usize),
+        Parameter::toggle("record", "Record Trajectory", d.record,
+            |b| b.parameters.record, |b, v| b.update_record(v)),
+        Parameter::number("recordinterval", "Record Interval (s)", (0.01, 2.0, 0.01), d.record_interval,
+            |b| b.parameters.record_interval, |b, v| b.parameters.record_interval = v),
+    ];
+    [count, steering_parameters(&d), behavior, boundary_parameters(&d), display].into_iter().flatten().collect()
+});
+
+// 2Dと3Dのボイドで共通のパラメータをBoidParametersの読み書きで宣言するため
+pub trait HasBoidParameters {
+    fn boid_parameters(&self) -> &BoidParameters;
+    fn boid_parameters_mut(&mut self) -> &mut BoidParameters;
+}
+
+// 分離・整列・結合と操舵・積分の方法 (dは既定値)
+pub fn steering_parameters<G: HasBoidParameters>(d: &BoidParameters) -> Vec<Parameter<G>> {
+    vec![
+        Parameter::number("separateforce", "Separation Force", (0.01, 1.0, 0.01), d.separate_force,
+            |b| b.boid_parameters().separate_force, |b, v| b.boid_parameters_mut().separate_force = v),
+        Parameter::number("alignforce", "Alignment Force", (0.01, 1.0, 0.01), d.alignment_force,
+            |b| b.boid_parameters().alignment_force, |b, v| b.boid_parameters_mut().alignment_force = v),
+        Parameter::number("cohesionforce", "Cohesion Force", (0.01, 1.0, 0.01), d.cohesion_force,
+            |b| b.boid_parameters().cohesion_force, |b, v| b.boid_parameters_mut().cohesion_force = v),
+        Parameter::number("separatedistance", "Separation Distance", (1.0, 100.0, 1.0), d.separate_distance,
+            |b| b.boid_parameters().separate_distance, |b, v| b.boid_parameters_mut().separate_distance = v),
+        Parameter::number("aligndistance", "Alignment Distance", (1.0, 100.0, 1.0), d.alignment_distance,
+            |b| b.boid_parameters().alignment_distance, |b, v| b.boid_parameters_mut().alignment_distance = v),
+        Parameter::number("cohesiondistance", "Cohesion Distance", (1.0, 100.0, 1.0), d.cohesion_distance,
+            |b| b.boid_parameters().cohesion_distance, |b, v| b.boid_parameters_mut().cohesion_distance = v),
+        Parameter::number("maxforce", "Max Force", (0.01, 1.0, 0.01), d.max_force,
+            |b| b.boid_parameters().max_force, |b, v| b.boid_parameters_mut().max_force = v),
+        Parameter::choice("steeringmodel", "Steering Model", SteeringModel::NAMES, d.steering_model as usize,
+            |b| b.boid_parameters().steering_model as usize, |b, i| b.boid_parameters_mut().steering_model = SteeringModel::from_value(i as f64)),
+        Parameter::choice("integrator", "Integrator", Integrator::NAMES, d.integrator as usize,
+            |b| b.boid_parameters().integrator as usize, |b, i| b.boid_parameters_mut().integrator = Integrator::from_value(i as f64)),
+    ]
+}
+
+// 画面端・壁の扱い
+pub fn boundary_parameters<G: HasBoidParameters>(d: &BoidParameters) -> Vec<Parameter<G>> {
+    vec![
+        Parameter::choice("boundarymode", "Boundary", BoundaryMode::NAMES, d.boundary_mode as usize,
+            |b| b.boid_parameters().boundary_mode as usize, |b, i| b.boid_parameters_mut().boundary_mode = BoundaryMode::from_value(i as f64)),
+        Parameter::number("wallmargin", "Wall Margin", (10.0, 200.0, 1.0), d.wall_margin,
+            |b| b.boid_parameters().wall_margin, |b, v| b.boid_parameters_mut().wall_margin = v),
+        Parameter::number("wallforce", "Wall Force", (0.0, 2.0, 0.05), d.wall_force,
+            |b| b.boid_parameters().wall_force, |b, v| b.boid_parameters_mut().wall_force = v),
+    ]
+}
+
+// 組み込みのプリセット
+const PRESETS: &[Preset] = &[
+    Preset { name: "Default", values: &[] },
+    Preset {
+        name: "Tight Flock",
+        values: &[
+            ("separateforce", 0.2), ("alignforce", 0.6), ("cohesionforce", 0.6),
+            ("separatedistance", 20.0), ("aligndistance", 80.0), ("cohesiondistance", 100.0),
+        ],
+    },
+    Preset {
+        name: "Loose Swarm",
+        values: &[("separateforce", 0.5), ("alignforce", 0.05), ("cohesionforce", 0.1), ("separatedistance", 40.0)],
+    },
+    Preset {
+        name: "Predator Chase",
+        values: &[("boidcount", 300.0), ("predatorcount", 3.0), ("panicdistance", 120.0)],
+    },
+    Preset {
+        name: "Walled Box",
+        values: &[("boundarymode", 2.0), ("wallmargin", 80.0), ("wallforce", 0.8)],
+    },
+    Preset {
+        name: "Windy Currents",
+        values: &[("windforce", 0.05), ("winddirection", 20.0), ("flowforce", 0.15), ("showflow", 1.0)],
+    },
+];
+
+// ポインタの位置に置く引力 (repulseなら斥力) の中心
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Attractor {
+    position: Point,
+    repulse: bool,
+    right_button: bool, // moveイベントにはボタンが入らないので押したときのものを覚えておく
 }
 
 impl Boid {
-    pub fn new(width: u32, height: u32) -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn new(width: u32, height: u32, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
         let parameter = BoidParameters {
+            seed,
             alignment_distance: rng.gen_range(10.0..100.0),
             cohesion_distance: rng.gen_range(10.0..100.0),
             separate_distance: rng.gen_range(10.0..100.0),
@@ -28,61 +217,358 @@ impl Boid {
             separate_force: rng.gen_range(0.05..0.7),
             ..BoidParameters::default()
         };
+        Self::with_parameters(width, height, parameter)
+    }
+
+    pub fn with_parameters(width: u32, height: u32, parameters: BoidParameters) -> Self {
+        let rng = StdRng::seed_from_u64(parameters.seed);
+        let flow = FlowField::new(parameters.seed);
         Boid {
             agents: Vec::new(),
-            width: width,
-            height: height,
-            parameters: parameter,
+            next_id: 0,
+            kinematics: Kinematics::new(),
+            width,
+            height,
+            parameters,
+            grid: SpatialGrid::new(),
+            columns: NeighborColumns::new(),
+            speed_limits: Vec::new(),
+            previous: Vec::new(),
+            obstacles: Vec::new(),
+            pending_polygon: Vec::new(),
+            waypoints: Vec::new(),
+            flow,
+            attractor: None,
+            rng,
+            time: 0.0,
+            next_sample: 0.0,
+            metrics: MetricsHistory::new(),
+            metrics_grid: SpatialGrid::new(),
+            trajectory: None,
+            recording: false,
+            next_record: 0.0,
+        }
+    }
+
+    // シードを変えたら乱数を初期化し直して、エージェントを置き直す
+    pub fn update_seed(&mut self, seed: u64) {
+        if self.parameters.seed != seed {
+            self.parameters.seed = seed;
+            self.rng = StdRng::seed_from_u64(seed);
+            self.flow = FlowField::new(seed);
+            self.spawn_agents();
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.parameters.seed
+    }
+
+    pub fn metrics(&self) -> &MetricsHistory {
+        &self.metrics
+    }
+
+    // 各エージェントの位置 (ネイティブでの確認用)
+    pub fn positions(&self) -> impl Iterator<Item = Point> + '_ {
+        self.kinematics.positions()
+    }
+
+    // 各エージェントの速度 (ネイティブでの確認用)
+    pub fn velocities(&self) -> impl Iterator<Item = Point> + '_ {
+        (0..self.kinematics.len()).map(|i| self.kinematics.velocity(i))
+    }
+
+    // 各エージェントに最後のupdateで掛かった加速度 (操舵力の合計 ネイティブでの確認用)
+    pub fn accelerations(&self) -> impl Iterator<Item = Point> + '_ {
+        (0..self.kinematics.len()).map(|i| Point::new(self.kinematics.ax[i] as f64, self.kinematics.ay[i] as f64))
+    }
+
+    // 位置と速度を決めてエージェントを1つ置く (ネイティブでの確認用 boid_countなどは変えない)
+    pub fn add_agent(&mut self, species: Species, position: Point, velocity: Point) {
+        let agent = self.new_agent(species);
+        self.agents.push(agent);
+        self.kinematics.push(position, velocity);
+    }
+
+    // 各エージェントの番号 (生まれたときに付けたもの)
+    pub fn ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.agents.iter().map(|agent| agent.id)
+    }
+
+    // 各エージェントの軌跡 (古い順)
+    pub fn trails(&self) -> impl Iterator<Item = &VecDeque<Point>> + '_ {
+        self.agents.iter().map(|agent| &agent.trail)
+    }
+
+    // 今のパラメータで新しく記録を始める (今の状態が最初のフレーム)
+    pub fn start_recording(&mut self) {
+        self.trajectory = Some(Trajectory::new(self.width, self.height, self.parameters.clone()));
+        self.recording = true;
+        self.next_record = self.time + self.parameters.record_interval;
+        self.record_frame();
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.recording = false;
+    }
+
+    pub fn trajectory(&self) -> Option<&Trajectory> {
+        self.trajectory.as_ref()
+    }
+
+    // 記録を止めて取り出す
+    pub fn take_trajectory(&mut self) -> Trajectory {
+        self.recording = false;
+        self.trajectory
+            .take()
+            .unwrap_or_else(|| Trajectory::new(self.width, self.height, self.parameters.clone()))
+    }
+
+    fn record_frame(&mut self) {
+        let Some(trajectory) = self.trajectory.as_mut() else {
+            return;
+        };
+        let frame = Frame {
+            time: self.time,
+            agents: self
+                .agents
+                .iter()
+                .enumerate()
+                .map(|(i, agent)| AgentState {
+                    id: agent.id,
+                    species: agent.species,
+                    position: self.kinematics.position(i),
+                    velocity: self.kinematics.velocity(i),
+                })
+                .collect(),
+        };
+        if !trajectory.push(frame) {
+            log!("Trajectory is full, recording stopped at {:.1}s", self.time);
+            self.recording = false;
         }
     }
 
-    pub fn update_alignment_distance(&mut self, distance: f64) {
-        self.parameters.alignment_distance = distance;
+    // パラメータの数だけ被食者と捕食者をランダムに置く
+    pub(super) fn spawn_agents(&mut self) {
+        self.agents.clear();
+        self.kinematics.clear();
+        self.next_id = 0;
+        for _ in 0..self.parameters.boid_count {
+            self.push_agent(Species::Prey);
+        }
+        for _ in 0..self.parameters.predator_count {
+            self.push_agent(Species::Predator);
+        }
     }
-    pub fn update_cohesion_distance(&mut self, distance: f64) {
-        self.parameters.cohesion_distance = distance;
+
+    // 画面内のランダムな位置と向きに置く
+    fn push_agent(&mut self, species: Species) {
+        let (position, velocity) = random_state(self.width, self.height, &mut self.rng);
+        let agent = self.new_agent(species);
+        self.agents.push(agent);
+        self.kinematics.push(position, velocity);
     }
-    pub fn update_separate_distance(&mut self, distance: f64) {
-        self.parameters.separate_distance = distance;
+
+    // 新しい番号を付けたエージェント
+    fn new_agent(&mut self, species: Species) -> BoidAgent {
+        self.next_id += 1;
+        BoidAgent::new(self.next_id - 1, species, &self.parameters)
     }
-    pub fn update_alignment_force(&mut self, force: f64) {
-        self.parameters.alignment_force = force;
+
+    pub fn update_pointer_tool(&mut self, tool: PointerTool) {
+        if tool != self.parameters.pointer_tool {
+            self.attractor = None;
+        }
+        self.parameters.pointer_tool = tool;
     }
-    pub fn update_cohesion_force(&mut self, force: f64) {
-        self.parameters.cohesion_force = force;
+
+    pub fn add_obstacle(&mut self, obstacle: Obstacle) {
+        self.obstacles.push(obstacle);
     }
-    pub fn update_separate_force(&mut self, force: f64) {
-        self.parameters.separate_force = force;
+
+    pub fn clear_obstacles(&mut self) {
+        self.obstacles.clear();
+        self.pending_polygon.clear();
     }
+
+    pub fn add_waypoint(&mut self, waypoint: Point) {
+        self.waypoints.push(waypoint);
+    }
+
+    pub fn waypoints(&self) -> &[Point] {
+        &self.waypoints
+    }
+
+    // 消したら全員最初の目標からやり直す
+    pub fn clear_waypoints(&mut self) {
+        self.waypoints.clear();
+        for agent in self.agents.iter_mut() {
+            agent.waypoint = 0;
+        }
+    }
+
+    // boid_countは被食者の数 捕まって減った分はスライダーを動かしたときに戻す
     pub fn update_boid_count(&mut self, size: usize) {
         if self.parameters.boid_count != size {
-            if self.parameters.boid_count < size {
-                for _ in 0..(size - self.parameters.boid_count) {
-                    let mut rng = rand::thread_rng();
-                    let agent = BoidAgent {
-                        position: Point {
-                            x: rng.gen_range(0.0..self.width as f64),
-                            y: rng.gen_range(0.0..self.height as f64),
-                        },
-                        velocity: Point {
-                            x: rng.gen_range(-1.0..1.0),
-                            y: rng.gen_range(-1.0..1.0),
-                        },
-                        acceleration: Point {
-                            x: 0.0,
-                            y: 0.0,
-                        },
-                        size: self.parameters.boid_size,
-                    };
-                    self.agents.push(agent);
-                }
+            self.resize_species(Species::Prey, size);
+        }
+        self.parameters.boid_count = size;
+    }
+    pub fn update_predator_count(&mut self, size: usize) {
+        if self.parameters.predator_count != size {
+            self.resize_species(Species::Predator, size);
+        }
+        self.parameters.predator_count = size;
+    }
+    // 切り替えたときだけ記録を始める・止める (いっぱいになって止まったときにまた始めないように)
+    pub fn update_record(&mut self, record: bool) {
+        if self.parameters.record != record {
+            self.parameters.record = record;
+            if record {
+                self.start_recording();
             } else {
-                for _ in 0..(self.parameters.boid_count - size) {
-                    self.agents.pop();
-                }
+                self.stop_recording();
             }
         }
-        self.parameters.boid_count = size;
+    }
+
+    // speciesのエージェントの数をsizeに合わせる
+    fn resize_species(&mut self, species: Species, size: usize) {
+        let count = self.agents.iter().filter(|agent| agent.species == species).count();
+        for _ in count..size {
+            self.push_agent(species);
+        }
+        for _ in size..count {
+            if let Some(i) = self.agents.iter().rposition(|agent| agent.species == species) {
+                self.agents.remove(i);
+                self.kinematics.remove(i);
+            }
+        }
+    }
+}
+
+// 画面端の扱い
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BoundaryMode {
+    Wrap,     // 反対側から出てくる 近傍との距離も端をまたいだ近い方で測る
+    Bounce,   // 端で跳ね返る
+    SoftWall, // 端からwall_marginの範囲で内側に舵を切る (それでも出たら跳ね返る)
+}
+
+impl BoundaryMode {
+    pub const NAMES: &'static [&'static str] = &["Wrap", "Bounce", "Soft Wall"];
+
+    pub fn from_value(value: f64) -> Self {
+        match value.round() as i64 {
+            1 => BoundaryMode::Bounce,
+            2 => BoundaryMode::SoftWall,
+            _ => BoundaryMode::Wrap,
+        }
+    }
+}
+
+// クリックしたときの動作
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PointerTool {
+    Obstacle,  // 障害物を置く・消す
+    Attractor, // 押している間、ポインタの位置に引力 (Shiftか右ボタンで斥力) を置く
+    Waypoint,  // 目標を置く・消す
+}
+
+impl PointerTool {
+    pub const NAMES: &'static [&'static str] = &["Obstacle", "Attractor", "Waypoint"];
+
+    pub fn from_value(value: f64) -> Self {
+        match value.round() as i64 {
+            1 => PointerTool::Attractor,
+            2 => PointerTool::Waypoint,
+            _ => PointerTool::Obstacle,
+        }
+    }
+}
+
+// 種
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Species {
+    Prey,     // 群れをつくり、捕食者から逃げる
+    Predator, // 一番近い被食者を追いかける
+}
+
+impl Species {
+    pub fn rgb(&self) -> (u8, u8, u8) {
+        match self {
+            Species::Prey => PREY_RGB,
+            Species::Predator => PREDATOR_RGB,
+        }
+    }
+}
+
+// エージェントの色の付け方
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorMode {
+    Species, // 種ごとの色 (もともとの描き方)
+    Heading, // 進行方向を色相にする
+    Speed,   // 遅いと青、速いと赤
+    Density, // 近傍が少ないと青、多いと赤
+}
+
+impl ColorMode {
+    pub const NAMES: &'static [&'static str] = &["Species", "Heading", "Speed", "Density"];
+
+    pub fn from_value(value: f64) -> Self {
+        match value.round() as i64 {
+            1 => ColorMode::Heading,
+            2 => ColorMode::Speed,
+            3 => ColorMode::Density,
+            _ => ColorMode::Species,
+        }
+    }
+}
+
+// 積分法
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Integrator {
+    Euler,             // 今の速度で位置を進めてから速度を更新する
+    SemiImplicitEuler, // 速度を更新してから新しい速度で位置を進める (もともとの更新順 こちらの方が安定する)
+}
+
+impl Integrator {
+    pub const NAMES: &'static [&'static str] = &["Euler", "Semi-Implicit Euler"];
+
+    pub fn from_value(value: f64) -> Self {
+        if value >= 0.5 { Integrator::SemiImplicitEuler } else { Integrator::Euler }
+    }
+
+    // dt秒ぶん進める 速度はmax_speedで制限する
+    pub fn step<V>(&self, position: &mut V, velocity: &mut V, acceleration: V, dt: f64, limit: impl Fn(V) -> V)
+    where
+        V: Copy + std::ops::Add<Output = V> + std::ops::Mul<f64, Output = V>,
+    {
+        match self {
+            Integrator::Euler => {
+                *position = *position + *velocity * dt;
+                *velocity = limit(*velocity + acceleration * dt);
+            }
+            Integrator::SemiImplicitEuler => {
+                *velocity = limit(*velocity + acceleration * dt);
+                *position = *position + *velocity * dt;
+            }
+        }
+    }
+}
+
+// 操舵モデル
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SteeringModel {
+    Direct,   // 各ルールの向きに重みを掛けて加速度に足す (もともとのモデル)
+    Reynolds, // 望ましい速度 - 今の速度 を操舵力とし、合計をmax_forceで制限する
+}
+
+impl SteeringModel {
+    pub const NAMES: &'static [&'static str] = &["Direct", "Reynolds"];
+
+    pub fn from_value(value: f64) -> Self {
+        if value >= 0.5 { SteeringModel::Reynolds } else { SteeringModel::Direct }
     }
 }
 
@@ -91,13 +577,85 @@ impl Boid {
 pub struct BoidParameters {
     pub boid_size: f64,
     pub boid_count: usize,
-    pub max_speed: f64,
+    pub max_speed: f64, // px/s
+    pub max_force: f64, // 操舵力の上限 (REFERENCE_FPSの1フレームあたり)
+    pub integrator: Integrator,
+    pub steering_model: SteeringModel,
     pub separate_force: f64,
     pub separate_distance: f64,
     pub alignment_force: f64,
     pub alignment_distance: f64,
     pub cohesion_force: f64,
     pub cohesion_distance: f64,
+    pub view_angle: f64, // 視野角 (度) 進行方向を中心にこの角度の範囲だけが見える
+    pub separate_view_angle: Option<f64>, // ルールごとの視野角 Noneならview_angleを使う
+    pub alignment_view_angle: Option<f64>,
+    pub cohesion_view_angle: Option<f64>,
+    pub show_perception: bool, // デバッグ用 perception_agentの視野と近傍を描画する
+    pub perception_agent: usize,
+    pub avoidance_force: f64, // 障害物回避の強さ
+    pub look_ahead: f64,      // 障害物をどこまで先まで見て避けるか
+    pub obstacle_radius: f64, // クリックで置く円の障害物の半径
+    pub predator_count: usize,
+    pub predator_size: f64,
+    pub predator_max_speed: f64, // px/s
+    pub chase_force: f64,     // 捕食者が被食者を追いかける強さ
+    pub flee_force: f64,      // 被食者が捕食者から逃げる強さ
+    pub panic_distance: f64,  // 被食者が捕食者に気づいて逃げ始める距離
+    pub catch_distance: f64,  // 捕食者がこの距離まで近づいたら捕まえる
+    pub respawn_prey: bool,   // 捕まった被食者をランダムな位置に出し直す (falseなら消える)
+    pub seed: u64,            // 初期配置などに使う乱数のシード
+    pub boundary_mode: BoundaryMode,
+    pub wall_margin: f64, // SoftWallで壁を避け始める距離
+    pub wall_force: f64,  // SoftWallで壁を避ける強さ
+    pub show_metrics: bool,    // 群れの指標のグラフを描く
+    pub cluster_distance: f64, // この距離以内でつながっている被食者を1つのかたまりと数える
+    pub color_mode: ColorMode,
+    pub trails: bool,          // 軌跡を描く
+    pub trail_length: usize,   // 軌跡に残す位置の数 (updateの回数)
+    pub record: bool,          // 位置と速度を記録する
+    pub record_interval: f64,  // 記録する間隔 (秒)
+    pub pointer_tool: PointerTool,
+    pub attractor_radius: f64,   // 引力・斥力が届く距離
+    pub attractor_strength: f64, // 引力・斥力の強さ (中心ほど強い)
+    pub wind_force: f64,         // 一定の向きの風の強さ
+    pub wind_direction: f64,     // 風の向き (度 x軸から時計回り)
+    pub flow_force: f64,         // 流れの場に沿う強さ
+    pub flow_scale: f64,         // 流れの場の模様の大きさ (px)
+    pub flow_speed: f64,         // 流れの場が変わる速さ (1秒あたりのノイズの周期)
+    pub show_flow: bool,         // 流れの場を矢印で描く
+    pub goal_force: f64,         // 目標に向かう強さ
+    pub waypoint_radius: f64,    // 目標にこの距離まで近づいたら次の目標へ
+}
+
+impl BoidParameters {
+    // 視野の半分の角度のcos (内積との比較用)
+    fn view_cos(&self, angle: Option<f64>) -> f64 {
+        let angle = angle.unwrap_or(self.view_angle).clamp(0.0, 360.0);
+        (angle / 2.0).to_radians().cos()
+    }
+
+    pub fn size(&self, species: Species) -> f64 {
+        match species {
+            Species::Prey => self.boid_size,
+            Species::Predator => self.predator_size,
+        }
+    }
+
+    pub fn max_speed(&self, species: Species) -> f64 {
+        match species {
+            Species::Prey => self.max_speed,
+            Species::Predator => self.predator_max_speed,
+        }
+    }
+
+    // 近傍探索で一番遠くまで見る距離
+    fn neighbor_radius(&self) -> f64 {
+        self.separate_distance
+            .max(self.alignment_distance)
+            .max(self.cohesion_distance)
+            .max(if self.predator_count > 0 { self.panic_distance } else { 0.0 })
+    }
 }
 
 impl Default for BoidParameters {
@@ -105,45 +663,101 @@ impl Default for BoidParameters {
         BoidParameters {
             boid_size: 15.0,
             boid_count: 100,
-            max_speed: 3.0,
+            max_speed: 180.0,
+            max_force: 0.2,
+            integrator: Integrator::SemiImplicitEuler,
+            steering_model: SteeringModel::Direct,
             separate_force: 0.35,
             separate_distance: 25.0,
             alignment_force: 0.15,
             alignment_distance: 50.0,
             cohesion_force: 0.15,
             cohesion_distance: 50.0,
+            view_angle: 360.0,
+            separate_view_angle: None,
+            alignment_view_angle: None,
+            cohesion_view_angle: None,
+            show_perception: false,
+            perception_agent: 0,
+            avoidance_force: 1.0,
+            look_ahead: 50.0,
+            obstacle_radius: 30.0,
+            predator_count: 0,
+            predator_size: 25.0,
+            predator_max_speed: 210.0,
+            chase_force: 0.4,
+            flee_force: 0.6,
+            panic_distance: 80.0,
+            catch_distance: 8.0,
+            respawn_prey: true,
+            seed: 0,
+            boundary_mode: BoundaryMode::Wrap,
+            wall_margin: 50.0,
+            wall_force: 0.5,
+            show_metrics: false,
+            cluster_distance: 50.0,
+            color_mode: ColorMode::Species,
+            trails: false,
+            trail_length: 20,
+            record: false,
+            record_interval: 0.1,
+            pointer_tool: PointerTool::Obstacle,
+            attractor_radius: 150.0,
+            attractor_strength: 0.5,
+            wind_force: 0.0,
+            wind_direction: 0.0,
+            flow_force: 0.0,
+            flow_scale: 300.0,
+            flow_speed: 0.1,
+            show_flow: false,
+            goal_force: 0.2,
+            waypoint_radius: 40.0,
         }
     }
 }
 
+// 位置と速度以外の状態 (位置と速度はBoid::kinematicsに同じ並びで持つ)
 struct BoidAgent {
-    position: Point,
-    velocity: Point,
-    acceleration: Point,
+    id: usize, // 生まれたときに付ける番号 (消えても他のエージェントの番号は変わらない)
+    species: Species,
     size: f64,
+    neighbors: usize,        // 近傍探索の半径内にいる他のエージェントの数
+    trail: VecDeque<Point>, // 最近の位置 (古い順)
+    waypoint: usize,         // 今向かっている目標の番号 (waypointsの数で割った余りを使う)
 }
 
 impl BoidAgent {
+    fn new(id: usize, species: Species, parameters: &BoidParameters) -> Self {
+        BoidAgent {
+            id,
+            species,
+            size: parameters.size(species),
+            neighbors: 0,
+            trail: VecDeque::new(),
+            waypoint: 0,
+        }
+    }
+
     // 位置と向きから描画用の三角形の頂点を返す
-    pub fn triangle(&self) -> [Point; 3] {
-        let angle: f64 = if self.velocity.x == 0.0 && self.velocity.y == 0.0 {
+    pub fn triangle(&self, position: Point, velocity: Point) -> [Point; 3] {
+        let angle: f64 = if velocity.x == 0.0 && velocity.y == 0.0 {
             0.0
         } else {
-            self.velocity.y.atan2(self.velocity.x)
+            velocity.y.atan2(velocity.x)
         };
 
         let sendo = 0.6; // 三角形の尖り具合
 
-        let front_x = self.position.x + self.size*angle.cos();
-        let front_y = self.position.y + self.size*angle.sin();
+        let front_x = position.x + self.size*angle.cos();
+        let front_y = position.y + self.size*angle.sin();
 
         let left_angle = angle + (2.0*PI / 3.0);
-        let left_x = self.position.x + (self.size*sendo) * left_angle.cos();
-        let left_y = self.position.y + (self.size*sendo) * left_angle.sin();
+        let left_x = position.x + (self.size*sendo) * left_angle.cos();
+        let left_y = position.y + (self.size*sendo) * left_angle.sin();
 
         let right_angle = angle - (2.0*PI / 3.0);
-        let right_x = self.position.x + (self.size*sendo) * right_angle.cos();
-        let right_y = self.position.y + (self.size*sendo) * right_angle.sin();
+        let right_x = position.x + (self.size*sendo) * right_angle.cos();
+        let right_y = position.y + (self.size*sendo) * right_angle.sin();
 
         [
             Point{x: front_x, y: front_y},
@@ -153,217 +767,754 @@ impl BoidAgent {
     }
 }
 
+// 画面内のランダムな (位置, 速度)
+fn random_state(width: u32, height: u32, rng: &mut impl Rng) -> (Point, Point) {
+    let position = Point {
+        x: rng.gen_range(0.0..width as f64),
+        y: rng.gen_range(0.0..height as f64),
+    };
+    let velocity = Point {
+        x: rng.gen_range(-REFERENCE_FPS..REFERENCE_FPS),
+        y: rng.gen_range(-REFERENCE_FPS..REFERENCE_FPS),
+    };
+    (position, velocity)
+}
+
+// 進行方向の単位ベクトル
+fn heading(velocity: Point) -> Option<Point> {
+    let speed = (velocity.x.powi(2) + velocity.y.powi(2)).sqrt();
+    if speed == 0.0 {
+        None
+    } else {
+        Some(Point::new(velocity.x / speed, velocity.y / speed))
+    }
+}
+
+impl HasBoidParameters for Boid {
+    fn boid_parameters(&self) -> &BoidParameters {
+        &self.parameters
+    }
+
+    fn boid_parameters_mut(&mut self) -> &mut BoidParameters {
+        &mut self.parameters
+    }
+}
+
 #[async_trait(?Send)]
 impl Game for Boid {
     async fn initialize(&self) -> Result<Box<dyn Game>> {
-        log!("Boid initialize");
-        let mut agents: Vec<BoidAgent> = vec![];
-        let mut rng = rand::thread_rng();
-        for _ in 0..self.parameters.boid_count {
-            let agent = BoidAgent {
-                position: Point {
-                    x: rng.gen_range(0.0..self.width as f64),
-                    y: rng.gen_range(0.0..self.height as f64),
-                },
-                velocity: Point {
-                    x: rng.gen_range(-1.0..1.0),
-                    y: rng.gen_range(-1.0..1.0),
-                },
-                acceleration: Point {
-                    x: 0.0,
-                    y: 0.0,
-                },
-                size: self.parameters.boid_size,
-            };
-            agents.push(agent);
-        }
-
-        Ok(Box::new(Boid {
-            agents: agents,
-            width: self.width,
-            height: self.height,
-            parameters: self.parameters.clone(),
-        }))
-    }
-
-    fn update(&mut self) {
-        // 分離
-        self.separate();
-        // 整列
-        self.alignment();
-        // 結合
-        self.cohesion();
-        for agent in &mut self.agents {
-            // 速度を更新
-            agent.velocity.x += agent.acceleration.x;
-            agent.velocity.y += agent.acceleration.y;
-            let speed = (agent.velocity.x.powi(2) + agent.velocity.y.powi(2)).sqrt();
-            if speed > self.parameters.max_speed {
-                agent.velocity.x = agent.velocity.x / speed * self.parameters.max_speed;
-                agent.velocity.y = agent.velocity.y / speed * self.parameters.max_speed;
-            }
+        //log!("Boid initialize: seed {}", self.parameters.seed);
+        let mut boid = Boid::with_parameters(self.width, self.height, self.parameters.clone());
+        boid.spawn_agents();
+        Ok(Box::new(boid))
+    }
 
-            // 位置を更新
-            agent.position.x += agent.velocity.x;
-            agent.position.y += agent.velocity.y;
-
-            // 加速度をリセット
-            agent.acceleration.x = 0.0;
-            agent.acceleration.y = 0.0;
-
-            // // 画面端で跳ね返る
-            // if agent.position.x < 0.0 || agent.position.x > self.width as f64 {
-            //     agent.velocity.x *= -1.0;
-            // }
-            // if agent.position.y < 0.0 || agent.position.y > self.height as f64 {
-            //     agent.velocity.y *= -1.0;
-            // }
-
-            // 画面端でループ
-            if agent.position.x < 0.0 {
-                agent.position.x = self.width as f64;
-            }
-            if agent.position.x > self.width as f64 {
-                agent.position.x = 0.0;
+    fn update(&mut self, dt: f64) {
+        // 近傍探索用のグリッドを作り直す (セルの大きさは一番遠くまで見るルールの距離)
+        let cell_size = self.parameters.neighbor_radius();
+        self.grid.rebuild(
+            self.kinematics.positions(),
+            self.width as f64,
+            self.height as f64,
+            cell_size,
+            self.parameters.boundary_mode == BoundaryMode::Wrap,
+        );
+
+        // 分離・整列・結合 (加速度をkinematicsに書く)
+        self.flock();
+
+        // 速度と位置を更新 (全エージェントまとめて)
+        self.previous.clear();
+        if !self.obstacles.is_empty() {
+            self.previous.extend(self.kinematics.positions());
+        }
+        self.kinematics.integrate(self.parameters.integrator, dt as f32, &self.speed_limits);
+
+        // 障害物にぶつかったら表面で止めて、めり込む向きの速度を消す
+        for (i, previous) in self.previous.iter().enumerate() {
+            for obstacle in &self.obstacles {
+                if let Some((contact, normal)) = obstacle.collide(*previous, self.kinematics.position(i)) {
+                    let mut velocity = self.kinematics.velocity(i);
+                    let inward = velocity.x * normal.x + velocity.y * normal.y;
+                    if inward < 0.0 {
+                        velocity.x -= inward * normal.x;
+                        velocity.y -= inward * normal.y;
+                    }
+                    self.kinematics.set_position(i, contact);
+                    self.kinematics.set_velocity(i, velocity);
+                }
             }
-            if agent.position.y < 0.0 {
-                agent.position.y = self.height as f64;
+        }
+
+        // 画面端
+        let (width, height) = (self.width as f32, self.height as f32);
+        match self.parameters.boundary_mode {
+            BoundaryMode::Wrap => self.kinematics.wrap(width, height),
+            BoundaryMode::Bounce | BoundaryMode::SoftWall => self.kinematics.reflect(width, height),
+        }
+        // ループ・跳ね返りした先が障害物の中なら外に出す
+        if !self.obstacles.is_empty() {
+            for i in 0..self.kinematics.len() {
+                for obstacle in &self.obstacles {
+                    let position = self.kinematics.position(i);
+                    if obstacle.contains(position) {
+                        self.kinematics.set_position(i, obstacle.push_out(position).0);
+                    }
+                }
             }
-            if agent.position.y > self.height as f64 {
-                agent.position.y = 0.0;
+        }
+
+        // 軌跡 (古いものから捨てる)
+        for (i, agent) in self.agents.iter_mut().enumerate() {
+            if self.parameters.trails {
+                agent.trail.push_back(self.kinematics.position(i));
+                while agent.trail.len() > self.parameters.trail_length {
+                    agent.trail.pop_front();
+                }
+            } else if !agent.trail.is_empty() {
+                agent.trail.clear();
             }
         }
+        self.catch_prey();
+        self.time += dt;
+        if self.time >= self.next_sample {
+            self.next_sample = self.time + SAMPLE_INTERVAL;
+            self.measure();
+        }
+        // 半ステップの余裕を見て、dtの倍数の間隔がずれないようにする
+        if self.recording && self.time >= self.next_record - dt / 2.0 {
+            self.next_record += self.parameters.record_interval;
+            self.record_frame();
+        }
         //log!("Boid update");
     }
 
     fn draw(&self, renderer: &Renderer2d) {
         renderer.clear();
-        for agent in &self.agents {
-            let triangle = agent.triangle();
-            renderer.triangle(triangle[0].x, triangle[0].y, triangle[1].x, triangle[1].y, triangle[2].x, triangle[2].y);
+        for obstacle in &self.obstacles {
+            obstacle.draw(renderer);
+        }
+        for (a, b) in self.pending_polygon.iter().zip(self.pending_polygon.iter().skip(1)) {
+            renderer.line(*a, *b, 2.0, "rgba(128, 128, 128, 0.8)");
+        }
+        for vertex in &self.pending_polygon {
+            renderer.circle(*vertex, 3.0, "rgba(128, 128, 128, 0.8)");
+        }
+        if self.parameters.show_flow {
+            let p = &self.parameters;
+            self.flow.draw(renderer, self.width as f64, self.height as f64, p.flow_scale, self.time * p.flow_speed);
+        }
+        self.draw_waypoints(renderer);
+        let colors: Vec<(u8, u8, u8)> = self
+            .agents
+            .iter()
+            .enumerate()
+            .map(|(i, agent)| self.agent_rgb(agent, self.kinematics.velocity(i)))
+            .collect();
+        if self.parameters.trails {
+            for (agent, color) in self.agents.iter().zip(&colors) {
+                self.draw_trail(renderer, agent, *color);
+            }
+        }
+        for (i, (agent, (r, g, b))) in self.agents.iter().zip(&colors).enumerate() {
+            let triangle = agent.triangle(self.kinematics.position(i), self.kinematics.velocity(i));
+            renderer.triangle(
+                triangle[0].x, triangle[0].y, triangle[1].x, triangle[1].y, triangle[2].x, triangle[2].y,
+                &format!("rgba({}, {}, {}, 0.9)", r, g, b),
+            );
+        }
+        if let Some(attractor) = &self.attractor {
+            let color = if attractor.repulse { REPULSOR_COLOR } else { ATTRACTOR_COLOR };
+            renderer.circle(attractor.position, self.parameters.attractor_radius, color);
+        }
+        if self.parameters.show_perception {
+            self.draw_perception(renderer);
+        }
+        if self.parameters.show_metrics {
+            self.metrics.draw_chart(renderer, self.height as f64);
         }
         //log!("Boid draw");
     }
 
-    fn update_parameter_from_html(&mut self){
-        let param_list: Vec<&str> = vec![
-            "boidcount",
-            "separateforce",
-            "alignforce",
-            "cohesionforce",
-            "separatedistance",
-            "aligndistance",
-            "cohesiondistance",
-        ];
-        for name in param_list {
-            let value = match engine::get_parameter_ui_value(name) {
-                Ok(val) => val,
-                Err(err) => {
-                    log!("Failed to get parameter value for {}: {:?}", name, err);
-                    continue;
-                }
+    // 障害物ツール
+    //   クリック: 円の障害物を置く
+    //   Ctrl+クリック: 多角形の頂点を追加 (最初の頂点をクリックすると閉じる)
+    //   右クリックかAlt+クリック: その位置の障害物を消す
+    // 引力ツール
+    //   押している間 (タッチも): ポインタの位置に引力 Shiftか右ボタンなら斥力
+    // 目標ツール
+    //   クリック: 目標を最後に追加
+    //   右クリックかAlt+クリック: その位置の目標を消す
+    fn handle_pointer(&mut self, event: &PointerEvent) {
+        if self.parameters.pointer_tool == PointerTool::Attractor {
+            self.attractor = match event.kind {
+                PointerKind::Down => Some(Attractor {
+                    position: event.position,
+                    repulse: event.shift || event.button == 2,
+                    right_button: event.button == 2,
+                }),
+                PointerKind::Move => self.attractor.map(|attractor| Attractor {
+                    position: event.position,
+                    repulse: event.shift || attractor.right_button,
+                    ..attractor
+                }),
+                PointerKind::Up => None,
             };
-            match name {
-                "boidcount" => self.update_boid_count(value as usize),
-                "separateforce" => self.update_separate_force(value),
-                "alignforce" => self.update_alignment_force(value),
-                "cohesionforce" => self.update_cohesion_force(value),
-                "separatedistance" => self.update_separate_distance(value),
-                "aligndistance" => self.update_alignment_distance(value),
-                "cohesiondistance" => self.update_cohesion_distance(value),
-                _ => log!("Unknown parameter: {}", name),
+            return;
+        }
+        if event.kind != PointerKind::Down {
+            return;
+        }
+        let position = event.position;
+        if self.parameters.pointer_tool == PointerTool::Waypoint {
+            // クリックで最後に追加、右クリックかAlt+クリックでその位置の目標を消す
+            if event.button == 2 || event.alt {
+                let radius_sq = self.parameters.waypoint_radius.powi(2);
+                self.waypoints.retain(|w| (w.x - position.x).powi(2) + (w.y - position.y).powi(2) >= radius_sq);
+            } else if event.button == 0 {
+                self.add_waypoint(position);
             }
+            return;
+        }
+        if event.button == 2 || event.alt {
+            self.obstacles.retain(|obstacle| !obstacle.contains(position));
+        } else if event.ctrl {
+            let closes = self.pending_polygon.first().is_some_and(|first| {
+                (first.x - position.x).powi(2) + (first.y - position.y).powi(2) < POLYGON_CLOSE_DISTANCE.powi(2)
+            });
+            if closes && self.pending_polygon.len() >= 3 {
+                let vertices = std::mem::take(&mut self.pending_polygon);
+                self.add_obstacle(Obstacle::Polygon { vertices });
+            } else {
+                self.pending_polygon.push(position);
+            }
+        } else if event.button == 0 {
+            self.add_obstacle(Obstacle::Circle { center: position, radius: self.parameters.obstacle_radius });
         }
     }
-}
 
-impl Boid {
-    fn separate(&mut self) {
-        for i in 0..self.agents.len() {
-            let mut count = 0;
-            let mut separate = Point{x: 0.0, y: 0.0};
-            for j in 0..self.agents.len() {
-                if i==j{
-                    continue;
-                }
-                let distance = (self.agents[i].position.x - self.agents[j].position.x).powi(2) + (self.agents[i].position.y - self.agents[j].position.y).powi(2);
-                if distance > 0. && distance < self.parameters.separate_distance.powi(2){
-                    separate.x += self.agents[i].position.x - self.agents[j].position.x;
-                    separate.y += self.agents[i].position.y - self.agents[j].position.y;
-                    count += 1;
-                }
+    fn name(&self) -> &'static str {
+        "boid"
+    }
+
+    fn parameters(&self) -> Vec<ParameterSpec> {
+        parameter::specs(&PARAMETERS)
+    }
+
+    fn parameter_values(&self) -> Vec<ParameterValue> {
+        parameter::values(&PARAMETERS, self)
+    }
+
+    fn parameter_defaults(&self) -> Vec<ParameterValue> {
+        // シードは今のものにする (既定値に戻してもエージェントを置き直さない)
+        let defaults = BoidParameters { seed: self.parameters.seed, ..BoidParameters::default() };
+        parameter::values(&PARAMETERS, &Boid::with_parameters(self.width, self.height, defaults))
+    }
+
+    fn handle_parameter(&mut self, change: &ParameterChange) {
+        parameter::apply(&PARAMETERS, self, change);
+    }
+
+    fn presets(&self) -> Vec<Preset> {
+        PRESETS.to_vec()
+    }
+
+    // エージェント・障害物・目標の位置を新しい画面の大きさに合わせて伸び縮みさせる
+    fn on_resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 || (width, height) == (self.width, self.height) {
+            return;
+        }
+        if self.width > 0 && self.height > 0 {
+            let (sx, sy) = (width as f64 / self.width as f64, height as f64 / self.height as f64);
+            let scale = |p: &mut Point| *p = Point::new(p.x * sx, p.y * sy);
+            for x in self.kinematics.x.iter_mut() {
+                *x *= sx as f32;
             }
-            if count > 0 {
-                separate.x /= count as f64;
-                separate.y /= count as f64;
-                let length = (separate.x.powi(2) + separate.y.powi(2)).sqrt();
-                separate.x /= length;
-                separate.y /= length;
-                separate.x *= self.parameters.separate_force;
-                separate.y *= self.parameters.separate_force;
+            for y in self.kinematics.y.iter_mut() {
+                *y *= sy as f32;
+            }
+            for agent in self.agents.iter_mut() {
+                agent.trail.iter_mut().for_each(scale);
+            }
+            for obstacle in self.obstacles.iter_mut() {
+                obstacle.rescale(sx, sy);
+            }
+            self.waypoints.iter_mut().for_each(scale);
+            self.pending_polygon.iter_mut().for_each(scale);
+            if let Some(attractor) = self.attractor.as_mut() {
+                scale(&mut attractor.position);
             }
-            self.agents[i].acceleration.x += separate.x;
-            self.agents[i].acceleration.y += separate.y;
         }
+        log!("Boid resized: {}x{} -> {}x{}", self.width, self.height, width, height);
+        self.width = width;
+        self.height = height;
     }
 
-    fn alignment(&mut self) {
-        for i in 0..self.agents.len() {
-            let mut count = 0;
-            let mut alignment = Point{x: 0.0, y: 0.0};
-            for j in 0..self.agents.len() {
-                if i==j{
-                    continue;
+    fn handle_action(&mut self, action: &str) {
+        match action {
+            "export-metrics" => {
+                if let Err(err) = engine::download("boid-metrics.csv", "text/csv", &self.metrics.to_csv()) {
+                    log!("Failed to export metrics: {:?}", err);
                 }
-                let distance = (self.agents[i].position.x - self.agents[j].position.x).powi(2) + (self.agents[i].position.y - self.agents[j].position.y).powi(2);
-                if distance > 1.0 && distance < self.parameters.alignment_distance.powi(2){
-                    alignment.x += self.agents[j].velocity.x;
-                    alignment.y += self.agents[j].velocity.y;
-                    count += 1;
+            }
+            "export-trajectory-csv" | "export-trajectory-json" => {
+                let Some(trajectory) = self.trajectory.as_ref().filter(|trajectory| !trajectory.is_empty()) else {
+                    log!("Nothing recorded yet");
+                    return;
+                };
+                let result = if action == "export-trajectory-csv" {
+                    engine::download("boid-trajectory.csv", "text/csv", &trajectory.to_csv())
+                } else {
+                    engine::download("boid-trajectory.json", "application/json", &trajectory.to_json())
+                };
+                if let Err(err) = result {
+                    log!("Failed to export trajectory: {:?}", err);
                 }
             }
-            if count > 0 {
-                alignment.x /= count as f64;
-                alignment.y /= count as f64;
-                let length = (alignment.x.powi(2) + alignment.y.powi(2)).sqrt();
-                alignment.x /= length;
-                alignment.y /= length;
-                alignment.x *= self.parameters.alignment_force;
-                alignment.y *= self.parameters.alignment_force;
+            "clear-waypoints" => self.clear_waypoints(),
+            _ => log!("Unknown action: {}", action),
+        }
+    }
+}
+
+impl Boid {
+    // color_modeに合わせたエージェントの色 (捕食者はいつも種の色)
+    fn agent_rgb(&self, agent: &BoidAgent, velocity: Point) -> (u8, u8, u8) {
+        if agent.species == Species::Predator {
+            return agent.species.rgb();
+        }
+        match self.parameters.color_mode {
+            ColorMode::Species => agent.species.rgb(),
+            ColorMode::Heading => {
+                let angle = velocity.y.atan2(velocity.x).to_degrees();
+                hsv_to_rgb(angle.rem_euclid(360.0), 1.0, 1.0)
+            }
+            ColorMode::Speed => {
+                let speed = (velocity.x.powi(2) + velocity.y.powi(2)).sqrt();
+                heat_rgb(speed / self.parameters.max_speed(agent.species))
+            }
+            ColorMode::Density => heat_rgb(agent.neighbors as f64 / DENSITY_SCALE),
+        }
+    }
+
+    // 古いところほど薄く描く ループで反対側に飛んだところはつながない
+    fn draw_trail(&self, renderer: &Renderer2d, agent: &BoidAgent, (r, g, b): (u8, u8, u8)) {
+        let count = agent.trail.len();
+        let (half_width, half_height) = (self.width as f64 / 2.0, self.height as f64 / 2.0);
+        for (k, (from, to)) in agent.trail.iter().zip(agent.trail.iter().skip(1)).enumerate() {
+            if (to.x - from.x).abs() > half_width || (to.y - from.y).abs() > half_height {
+                continue;
+            }
+            let alpha = TRAIL_ALPHA * (k + 1) as f64 / count as f64;
+            renderer.line(*from, *to, 1.5, &format!("rgba({}, {}, {}, {:.3})", r, g, b, alpha));
+        }
+    }
+
+    // デバッグ用 選んだエージェントの視野 (一番遠くまで見るルールの距離と視野角) と、その中にいる近傍を描く
+    fn draw_perception(&self, renderer: &Renderer2d) {
+        let i = self.parameters.perception_agent;
+        let Some(agent) = self.agents.get(i) else {
+            return;
+        };
+        let (position, velocity) = (self.kinematics.position(i), self.kinematics.velocity(i));
+        let p = &self.parameters;
+        let radius = p.separate_distance.max(p.alignment_distance).max(p.cohesion_distance);
+        let half_angle = p.view_angle.clamp(0.0, 360.0).to_radians() / 2.0;
+        let direction = velocity.y.atan2(velocity.x);
+        renderer.sector(position, radius, direction - half_angle, direction + half_angle, "rgba(255, 255, 0, 0.2)");
+
+        let view_cos = p.view_cos(None);
+        let heading = heading(velocity);
+        let torus = self.torus();
+        for j in self.grid.neighbors(position, radius) {
+            let other = &self.agents[j];
+            if i == j || other.species != agent.species {
+                continue;
+            }
+            let other_position = self.kinematics.position(j);
+            let Point { x: dx, y: dy } = offset(other_position, position, torus);
+            let distance = dx * dx + dy * dy;
+            if distance < radius.powi(2) && in_view(heading, dx, dy, distance) >= view_cos {
+                renderer.circle(other_position, other.size * 0.5, "red");
             }
-            self.agents[i].acceleration.x += alignment.x;
-            self.agents[i].acceleration.y += alignment.y;
         }
     }
 
-    fn cohesion(&mut self) {
-        for i in 0..self.agents.len() {
-            let mut count = 0;
-            let mut cohesion = Point{x: 0.0, y: 0.0};
-            for j in 0..self.agents.len() {
-                if i==j{
-                    continue;
+    // 進行方向look_ahead先までの線分が障害物に当たるか、障害物が近すぎるときに表面から離れる向きに舵を切る
+    // 近いほど強くする
+    fn avoidance(&self, position: Point, velocity: Point, heading: Option<Point>, max_speed: f64) -> Point {
+        let p = &self.parameters;
+        let mut avoidance = Point::new(0.0, 0.0);
+        if p.look_ahead <= 0.0 {
+            return avoidance;
+        }
+        let heading = heading.unwrap_or(Point::new(0.0, 0.0));
+        let ahead = Point::new(position.x + heading.x * p.look_ahead, position.y + heading.y * p.look_ahead);
+        for obstacle in &self.obstacles {
+            let (distance, normal) = match obstacle.collide(position, ahead) {
+                Some((contact, normal)) => {
+                    let distance = ((contact.x - position.x).powi(2) + (contact.y - position.y).powi(2)).sqrt();
+                    (distance, normal)
                 }
-                let distance = (self.agents[i].position.x - self.agents[j].position.x).powi(2) + (self.agents[i].position.y - self.agents[j].position.y).powi(2);
-                if distance > 0.5 && distance < self.parameters.cohesion_distance.powi(2){
-                    cohesion.x += self.agents[j].position.x;
-                    cohesion.y += self.agents[j].position.y;
-                    count += 1;
+                None => obstacle.surface(position),
+            };
+            if distance >= p.look_ahead {
+                continue;
+            }
+            let weight = p.avoidance_force * (1.0 - distance.max(0.0) / p.look_ahead);
+            let force = steer(normal, velocity, weight, max_speed, p);
+            avoidance.x += force.x;
+            avoidance.y += force.y;
+        }
+        avoidance
+    }
+
+    // ポインタの位置へ向かう (斥力なら離れる) 操舵力 中心に近いほど強くする
+    fn attraction(&self, position: Point, velocity: Point, max_speed: f64) -> Point {
+        let p = &self.parameters;
+        let Some(attractor) = &self.attractor else {
+            return Point::new(0.0, 0.0);
+        };
+        let mut direction = Point::new(attractor.position.x - position.x, attractor.position.y - position.y);
+        let distance = (direction.x.powi(2) + direction.y.powi(2)).sqrt();
+        if distance >= p.attractor_radius {
+            return Point::new(0.0, 0.0);
+        }
+        if attractor.repulse {
+            direction = Point::new(-direction.x, -direction.y);
+        }
+        let weight = p.attractor_strength * (1.0 - distance / p.attractor_radius);
+        steer(direction, velocity, weight, max_speed, p)
+    }
+
+    // 風と流れの場に沿う操舵力
+    fn environment(&self, position: Point, velocity: Point, max_speed: f64) -> Point {
+        let p = &self.parameters;
+        let mut force = Point::new(0.0, 0.0);
+        if p.wind_force > 0.0 {
+            let (sin, cos) = p.wind_direction.to_radians().sin_cos();
+            force = steer(Point::new(cos, sin), velocity, p.wind_force, max_speed, p);
+        }
+        if p.flow_force > 0.0 {
+            let direction = self.flow.direction(position, p.flow_scale, self.time * p.flow_speed);
+            let flow = steer(direction, velocity, p.flow_force, max_speed, p);
+            force.x += flow.x;
+            force.y += flow.y;
+        }
+        force
+    }
+
+    // waypoint番目の目標へ向かう操舵力と、次に向かう目標の番号 (着いていたら次の目標にする)
+    fn goal(&self, position: Point, velocity: Point, waypoint: usize, max_speed: f64) -> (Point, usize) {
+        let p = &self.parameters;
+        if self.waypoints.is_empty() || p.goal_force <= 0.0 {
+            return (Point::new(0.0, 0.0), waypoint);
+        }
+        let torus = self.torus();
+        let mut waypoint = waypoint % self.waypoints.len();
+        let mut direction = offset(position, self.waypoints[waypoint], torus);
+        if direction.x.powi(2) + direction.y.powi(2) < p.waypoint_radius.powi(2) {
+            waypoint = (waypoint + 1) % self.waypoints.len();
+            direction = offset(position, self.waypoints[waypoint], torus);
+        }
+        (steer(direction, velocity, p.goal_force, max_speed, p), waypoint)
+    }
+
+    // 目標を順につないで描く
+    fn draw_waypoints(&self, renderer: &Renderer2d) {
+        let radius = self.parameters.waypoint_radius;
+        for (i, waypoint) in self.waypoints.iter().enumerate() {
+            let next = self.waypoints[(i + 1) % self.waypoints.len()];
+            renderer.line(*waypoint, next, 1.0, WAYPOINT_COLOR);
+            renderer.circle(*waypoint, radius, WAYPOINT_COLOR);
+            renderer.text(Point::new(waypoint.x - 4.0, waypoint.y - 6.0), &(i + 1).to_string(), 12.0, "rgba(255, 200, 0, 0.9)");
+        }
+    }
+
+    // 画面端がつながっているときの (幅, 高さ)
+    fn torus(&self) -> Option<Point> {
+        (self.parameters.boundary_mode == BoundaryMode::Wrap).then_some(Point::new(self.width as f64, self.height as f64))
+    }
+
+    // SoftWallのとき、端からwall_marginの範囲にいたら内側に舵を切る
+    fn wall_avoidance(&self, position: Point, velocity: Point, max_speed: f64) -> Point {
+        let size = Point::new(self.width as f64, self.height as f64);
+        wall_avoidance(position, velocity, Point::ZERO, size, max_speed, &self.parameters)
+    }
+
+    // 被食者の群れの指標を記録する
+    fn measure(&mut self) {
+        let (positions, velocities): (Vec<Point>, Vec<Point>) = self
+            .agents
+            .iter()
+            .enumerate()
+            .filter(|(_, agent)| agent.species == Species::Prey)
+            .map(|(i, _)| (self.kinematics.position(i), self.kinematics.velocity(i)))
+            .unzip();
+        let torus = self.torus();
+        let distance = self.parameters.cluster_distance;
+        self.metrics_grid.rebuild(positions.iter().copied(), self.width as f64, self.height as f64, distance, torus.is_some());
+        let metrics = FlockMetrics::measure(&positions, &velocities, torus.map(|t| (t.x, t.y)), distance, &self.metrics_grid);
+        self.metrics.push(self.time, metrics);
+    }
+
+    // 一番近い被食者へのベクトル (捕食者は少ないので全部見る)
+    fn nearest_prey(&self, position: Point) -> Option<Point> {
+        let torus = self.torus();
+        self.agents
+            .iter()
+            .enumerate()
+            .filter(|(_, agent)| agent.species == Species::Prey)
+            .map(|(i, _)| offset(position, self.kinematics.position(i), torus))
+            .min_by(|a, b| (a.x.powi(2) + a.y.powi(2)).total_cmp(&(b.x.powi(2) + b.y.powi(2))))
+    }
+
+    // 捕食者に捕まった被食者をランダムな位置に出し直すか、消す
+    fn catch_prey(&mut self) {
+        let torus = self.torus();
+        let p = &self.parameters;
+        let catch_sq = p.catch_distance.powi(2);
+        let predators: Vec<Point> = self.agents
+            .iter()
+            .enumerate()
+            .filter(|(_, agent)| agent.species == Species::Predator)
+            .map(|(i, _)| self.kinematics.position(i))
+            .collect();
+        if predators.is_empty() {
+            return;
+        }
+        let caught: Vec<bool> = self.agents
+            .iter()
+            .enumerate()
+            .map(|(i, agent)| {
+                agent.species == Species::Prey
+                    && predators.iter().any(|predator| {
+                        let d = offset(self.kinematics.position(i), *predator, torus);
+                        d.x * d.x + d.y * d.y < catch_sq
+                    })
+            })
+            .collect();
+        if !p.respawn_prey {
+            let keep: Vec<bool> = caught.iter().map(|caught| !caught).collect();
+            let mut i = 0;
+            self.agents.retain(|_| {
+                i += 1;
+                keep[i - 1]
+            });
+            self.kinematics.retain(&keep);
+            return;
+        }
+        for (i, caught) in caught.into_iter().enumerate() {
+            if caught {
+                let (mut position, velocity) = random_state(self.width, self.height, &mut self.rng);
+                for obstacle in &self.obstacles {
+                    if obstacle.contains(position) {
+                        position = obstacle.push_out(position).0;
+                    }
                 }
+                // 出し直したものは別の個体として新しい番号を付ける
+                self.agents[i] = self.new_agent(Species::Prey);
+                self.kinematics.set(i, position, velocity);
             }
-            if count > 0 {
-                cohesion.x /= count as f64;
-                cohesion.y /= count as f64; // cohesionの平均
-                cohesion.x -= self.agents[i].position.x;
-                cohesion.y -= self.agents[i].position.y; // agentからみたcohesionのベクトル
-                let length = (cohesion.x.powi(2) + cohesion.y.powi(2)).sqrt();
-                cohesion.x /= length;
-                cohesion.y /= length; // cohesionの単位ベクトル
-                cohesion.x *= self.parameters.cohesion_force;
-                cohesion.y *= self.parameters.cohesion_force;
+        }
+    }
+
+    // 分離・整列・結合を1回の近傍走査でまとめて計算する
+    // 結果は加速度 (ax, ay) に書き込むので、走査中に位置と速度は変わらない (処理順に依存しない)
+    fn flock(&mut self) {
+        let p = &self.parameters;
+        let radius = p.neighbor_radius();
+        let torus = self.torus();
+        let panic_sq = p.panic_distance.powi(2);
+        let (separate_sq, alignment_sq, cohesion_sq) = (
+            p.separate_distance.powi(2),
+            p.alignment_distance.powi(2),
+            p.cohesion_distance.powi(2),
+        );
+        let (separate_cos, alignment_cos, cohesion_cos) = (
+            p.view_cos(p.separate_view_angle),
+            p.view_cos(p.alignment_view_angle),
+            p.view_cos(p.cohesion_view_angle),
+        );
+
+        self.speed_limits.clear();
+        self.speed_limits.resize(self.agents.len(), 0.0);
+        // 近傍の和はセルの順に並べ直したf32の列で取る
+        // 全方向が見えるときは丸め誤差で見えなくならないように-1より小さくする
+        let visible_cos = |cos: f64| if cos <= -1.0 + f64::EPSILON { -2.0 } else { cos as f32 };
+        let rules = NeighborRules {
+            torus: torus.map(|t| (t.x as f32, t.y as f32)),
+            radius_sq: (radius * radius) as f32,
+            separate_sq: separate_sq as f32,
+            alignment_sq: alignment_sq as f32,
+            cohesion_sq: cohesion_sq as f32,
+            panic_sq: panic_sq as f32,
+            separate_cos: visible_cos(separate_cos),
+            alignment_cos: visible_cos(alignment_cos),
+            cohesion_cos: visible_cos(cohesion_cos),
+        };
+        let agents = &self.agents;
+        self.columns.sum(&self.kinematics, &self.grid, radius, &rules, |i| agents[i].species == Species::Prey);
+        // セルの順に回す
+        for k in 0..self.agents.len() {
+            let i = self.grid.order()[k];
+            let species = self.agents[i].species;
+            let (position, velocity) = (self.kinematics.position(i), self.kinematics.velocity(i));
+            let heading = heading(velocity);
+            let max_speed = p.max_speed(species);
+            let flocking = species == Species::Prey;
+            let sums = self.columns.sums(k);
+            // 自分 (距離0) も数えているので除く
+            let neighbors = (sums.neighbors as usize).saturating_sub(1);
+            let point = |(x, y): (f32, f32)| Point::new(x as f64, y as f64);
+            let (separate, alignment, cohesion, flee) =
+                (point(sums.separate), point(sums.alignment), point(sums.cohesion), point(sums.flee));
+            let (separate_count, alignment_count, cohesion_count, flee_count) =
+                (sums.separate_count as usize, sums.alignment_count as usize, sums.cohesion_count as usize, sums.flee_count as usize);
+
+            let mut steering = Point::new(0.0, 0.0);
+            if separate_count > 0 {
+                let force = steer(separate, velocity, p.separate_force, max_speed, p);
+                steering.x += force.x;
+                steering.y += force.y;
+            }
+            if alignment_count > 0 {
+                let force = steer(alignment, velocity, p.alignment_force, max_speed, p);
+                steering.x += force.x;
+                steering.y += force.y;
+            }
+            if cohesion_count > 0 {
+                // agentからみた近傍の重心へのベクトル
+                let center = Point::new(cohesion.x / cohesion_count as f64, cohesion.y / cohesion_count as f64);
+                let force = steer(center, velocity, p.cohesion_force, max_speed, p);
+                steering.x += force.x;
+                steering.y += force.y;
             }
-            self.agents[i].acceleration.x += cohesion.x;
-            self.agents[i].acceleration.y += cohesion.y;
+            if p.steering_model == SteeringModel::Reynolds {
+                steering = steering.limited(p.max_force * FORCE_UNIT);
+            }
+
+            // 追跡と逃避 (群れのルールより優先する)
+            let pursuit = match species {
+                Species::Prey if flee_count > 0 => steer(flee, velocity, p.flee_force, max_speed, p),
+                Species::Predator => match self.nearest_prey(position) {
+                    Some(target) => steer(target, velocity, p.chase_force, max_speed, p),
+                    None => Point::new(0.0, 0.0),
+                },
+                _ => Point::new(0.0, 0.0),
+            };
+            steering.x += pursuit.x;
+            steering.y += pursuit.y;
+
+            // 壁
+            let wall = self.wall_avoidance(position, velocity, max_speed);
+            steering.x += wall.x;
+            steering.y += wall.y;
+
+            // ポインタの引力・斥力
+            let attraction = self.attraction(position, velocity, max_speed);
+            steering.x += attraction.x;
+            steering.y += attraction.y;
+
+            // 風と流れの場
+            let environment = self.environment(position, velocity, max_speed);
+            steering.x += environment.x;
+            steering.y += environment.y;
+
+            // 目標 (群れをつくる被食者だけ)
+            let (goal, waypoint) = if flocking {
+                self.goal(position, velocity, self.agents[i].waypoint, max_speed)
+            } else {
+                (Point::new(0.0, 0.0), 0)
+            };
+            steering.x += goal.x;
+            steering.y += goal.y;
+
+            // 障害物回避 (他のルールより優先したいのでmax_forceの制限の外で足す)
+            let avoidance = self.avoidance(position, velocity, heading, max_speed);
+            steering.x += avoidance.x;
+            steering.y += avoidance.y;
+            self.kinematics.ax[i] = steering.x as f32;
+            self.kinematics.ay[i] = steering.y as f32;
+            self.speed_limits[i] = max_speed as f32;
+            self.agents[i].neighbors = neighbors;
+            self.agents[i].waypoint = waypoint;
+        }
+    }
+}
+
+// 0..limitの外に出ていたら内側に折り返して、速度を内向きにする
+pub(super) fn reflect(position: f64, velocity: f64, limit: f64) -> (f64, f64) {
+    if position < 0.0 {
+        ((-position).min(limit), velocity.abs())
+    } else if position > limit {
+        ((2.0 * limit - position).max(0.0), -velocity.abs())
+    } else {
+        (position, velocity)
+    }
+}
+
+// fromからtoへのベクトル (2Dと3Dで共通)
+// torusが各軸の長さのときは端がつながっているとして、一番近い像へのベクトルにする
+pub(super) fn offset<V: Vector>(from: V, to: V, torus: Option<V>) -> V {
+    let d = to - from;
+    match torus {
+        Some(size) => d.zip_map(size, |d, size| d - (d / size).round() * size),
+        None => d,
+    }
+}
+
+// 進行方向と、agentから見た相手の方向のなす角のcos
+// (dx, dy)は相手からagentへのベクトル 進行方向が無い (止まっている) ときは全方向が見える
+fn in_view(heading: Option<Point>, dx: f64, dy: f64, distance_sq: f64) -> f64 {
+    match heading {
+        Some(h) if distance_sq > 0.0 => -(h.x * dx + h.y * dy) / distance_sq.sqrt(),
+        _ => 1.0,
+    }
+}
+
+// directionの方へ向かうための操舵力 (px/s² 2Dと3Dで共通)
+pub(super) fn steer<V: Vector>(direction: V, velocity: V, weight: f64, max_speed: f64, p: &BoidParameters) -> V {
+    match p.steering_model {
+        SteeringModel::Direct => direction.scaled(weight * FORCE_UNIT),
+        SteeringModel::Reynolds => {
+            // 最高速でdirectionへ向かう速度との差 (重み1なら1フレームで埋める)
+            (direction.scaled(max_speed) - velocity) * (weight * REFERENCE_FPS)
         }
     }
-}
\ No newline at end of file
+}
+
+// SoftWallのとき、lowからhighまでの箱の面からwall_marginの範囲にいたら内側に舵を切る 面に近いほど強くする
+pub(super) fn wall_avoidance<V: Vector>(position: V, velocity: V, low: V, high: V, max_speed: f64, p: &BoidParameters) -> V {
+    if p.boundary_mode != BoundaryMode::SoftWall || p.wall_margin <= 0.0 {
+        return V::ZERO;
+    }
+    let push = |distance: f64| (1.0 - distance.max(0.0) / p.wall_margin).max(0.0);
+    let direction = (position - low).zip_map(high - position, |near, far| push(near) - push(far));
+    let strength = (0..V::DIM).map(|i| direction.axis(i).abs()).fold(0.0, f64::max);
+    if strength == 0.0 {
+        return V::ZERO;
+    }
+    steer(direction, velocity, p.wall_force * strength, max_speed, p)
+}
+
+// 色相h (度)、彩度s、明度v (0..1) からRGB
+pub fn hsv_to_rgb(h: f64, s: f64, v: f64) -> (u8, u8, u8) {
+    let c = v * s;
+    let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let m = v - c;
+    (((r + m) * 255.0) as u8, ((g + m) * 255.0) as u8, ((b + m) * 255.0) as u8)
+}
+
+// 0で青、1で赤 (範囲外は端の色)
+pub fn heat_rgb(t: f64) -> (u8, u8, u8) {
+    hsv_to_rgb(240.0 * (1.0 - t.clamp(0.0, 1.0)), 1.0, 1.0)
+}
@@ -0,0 +1,412 @@
+// 3次元のボイド 箱の中を飛ぶ群れを透視投影で2Dのcanvasに描く
+// ルールとパラメータは2Dのボイドと同じものを使う
+use anyhow::Result;
+use async_trait::async_trait;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{f64::consts::PI, sync::LazyLock};
+
+use crate::engine::{Game, Point, PointerEvent, PointerKind, Renderer2d, Vec3, Vector};
+use crate::parameter::{self, Parameter, ParameterChange, ParameterSpec, ParameterValue, Preset};
+use super::boid::{
+    boundary_parameters, offset, reflect, steer, steering_parameters, wall_avoidance, BoidParameters, BoundaryMode,
+    HasBoidParameters, SteeringModel, FORCE_UNIT, REFERENCE_FPS,
+};
+use super::grid::SpatialGrid3d;
+
+const BOX_SIZE: f64 = 600.0; // 箱の一辺 (中心が原点)
+const BOX_COLOR: &str = "rgba(128, 128, 128, 0.5)";
+const ORBIT_SPEED: f64 = 0.01; // ドラッグ1pxあたりの回転 (ラジアン)
+const NEAR: f64 = 1.0;         // これよりカメラに近いものは描かない
+
+// パネルに出すパラメータ (2Dのボイドのうち3Dでも使うもの 既定値はBoid3d::newと同じ)
+static PARAMETERS: LazyLock<Vec<Parameter<Boid3d>>> = LazyLock::new(|| {
+    let d = default_parameters();
+    let count: Vec<Parameter<Boid3d>> = vec![
+        Parameter::integer("boidcount", "Number of Boid", (1.0, 1000.0), d.boid_count as f64,
+            |b| b.parameters.boid_count as u64, |b, v| b.update_boid_count(v as usize)),
+    ];
+    let seed: Vec<Parameter<Boid3d>> = vec![
+        Parameter::integer("seed", "Seed", (0.0, 4294967295.0), d.seed as f64,
+            |b| b.parameters.seed, |b, v| b.update_seed(v)),
+    ];
+    [count, steering_parameters(&d), seed, boundary_parameters(&d)].into_iter().flatten().collect()
+});
+
+// 組み込みのプリセット
+const PRESETS: &[Preset] = &[
+    Preset { name: "Default", values: &[] },
+    Preset {
+        name: "Tight Flock",
+        values: &[
+            ("separateforce", 0.2), ("alignforce", 0.6), ("cohesionforce", 0.6),
+            ("separatedistance", 20.0), ("aligndistance", 80.0), ("cohesiondistance", 100.0),
+        ],
+    },
+    Preset {
+        name: "Loose Swarm",
+        values: &[("separateforce", 0.5), ("alignforce", 0.05), ("cohesionforce", 0.1), ("separatedistance", 40.0)],
+    },
+];
+
+// 3Dのボイドの既定値
+fn default_parameters() -> BoidParameters {
+    BoidParameters {
+        boundary_mode: BoundaryMode::SoftWall, // 箱の中を飛んでいるように見せる
+        ..BoidParameters::default()
+    }
+}
+
+// 注視点 (箱の中心) のまわりを回るカメラ
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+    pub yaw: f64,      // Y軸まわりの回転 (ラジアン)
+    pub pitch: f64,    // X軸まわりの回転 (ラジアン)
+    pub distance: f64, // 注視点からの距離
+    pub fov: f64,      // 縦の画角 (度)
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Camera {
+            yaw: 0.5,
+            pitch: 0.3,
+            distance: BOX_SIZE * 1.8,
+            fov: 60.0,
+        }
+    }
+}
+
+impl Camera {
+    // カメラから見た座標 (右が+x、上が+y、奥が+z)
+    pub fn to_view(&self, p: Vec3) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let x = p.x * cos_yaw - p.z * sin_yaw;
+        let z = p.x * sin_yaw + p.z * cos_yaw;
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        let y = p.y * cos_pitch - z * sin_pitch;
+        let z = p.y * sin_pitch + z * cos_pitch;
+        Vec3::new(x, y, z + self.distance)
+    }
+
+    // 画面上の位置と拡大率 カメラより手前 (後ろ) にあるときはNone
+    pub fn project(&self, p: Vec3, width: f64, height: f64) -> Option<(Point, f64)> {
+        let view = self.to_view(p);
+        if view.z <= NEAR {
+            return None;
+        }
+        let focal = height / 2.0 / (self.fov.to_radians() / 2.0).tan();
+        let scale = focal / view.z;
+        Some((Point::new(width / 2.0 + view.x * scale, height / 2.0 - view.y * scale), scale))
+    }
+
+    // ドラッグした分だけ回す 真上・真下は越えない
+    pub fn orbit(&mut self, dx: f64, dy: f64) {
+        self.yaw += dx * ORBIT_SPEED;
+        self.pitch = (self.pitch + dy * ORBIT_SPEED).clamp(-PI / 2.0 + 0.01, PI / 2.0 - 0.01);
+    }
+}
+
+struct Agent3d {
+    position: Vec3,
+    velocity: Vec3,
+}
+
+pub struct Boid3d {
+    agents: Vec<Agent3d>,
+    width: u32,  // 画面の幅
+    height: u32, // 画面の高さ
+    parameters: BoidParameters,
+    camera: Camera,
+    drag: Option<Point>, // ドラッグ中なら直前のポインタの位置
+    steering: Vec<Vec3>, // flockの計算結果 (agentsとは別のバッファ)
+    grid: SpatialGrid3d, // 近傍探索用 flockのたびに作り直す
+    rng: StdRng,
+}
+
+impl Boid3d {
+    pub fn new(width: u32, height: u32, seed: u64) -> Self {
+        Self::with_parameters(width, height, BoidParameters { seed, ..default_parameters() })
+    }
+
+    pub fn with_parameters(width: u32, height: u32, parameters: BoidParameters) -> Self {
+        let rng = StdRng::seed_from_u64(parameters.seed);
+        Boid3d {
+            agents: Vec::new(),
+            width,
+            height,
+            parameters,
+            camera: Camera::default(),
+            drag: None,
+            steering: Vec::new(),
+            grid: SpatialGrid3d::new(),
+            rng,
+        }
+    }
+
+    pub fn update_boid_count(&mut self, size: usize) {
+        if self.agents.len() < size {
+            for _ in self.agents.len()..size {
+                let agent = self.random_agent();
+                self.agents.push(agent);
+            }
+        } else {
+            self.agents.truncate(size);
+        }
+        self.parameters.boid_count = size;
+    }
+
+    // シードを変えたら乱数を初期化し直して、エージェントを置き直す
+    pub fn update_seed(&mut self, seed: u64) {
+        if self.parameters.seed != seed {
+            self.parameters.seed = seed;
+            self.rng = StdRng::seed_from_u64(seed);
+            self.agents.clear();
+            self.update_boid_count(self.parameters.boid_count);
+        }
+    }
+
+    // 各エージェントの位置 (ネイティブでの確認用)
+    pub fn positions(&self) -> impl Iterator<Item = Vec3> + '_ {
+        self.agents.iter().map(|agent| agent.position)
+    }
+
+    fn random_agent(&mut self) -> Agent3d {
+        let half = BOX_SIZE / 2.0;
+        Agent3d {
+            position: Vec3::new(
+                self.rng.gen_range(-half..half),
+                self.rng.gen_range(-half..half),
+                self.rng.gen_range(-half..half),
+            ),
+            velocity: Vec3::new(
+                self.rng.gen_range(-REFERENCE_FPS..REFERENCE_FPS),
+                self.rng.gen_range(-REFERENCE_FPS..REFERENCE_FPS),
+                self.rng.gen_range(-REFERENCE_FPS..REFERENCE_FPS),
+            ),
+        }
+    }
+
+    // 箱の端がつながっているときの一辺
+    fn torus(&self) -> Option<Vec3> {
+        (self.parameters.boundary_mode == BoundaryMode::Wrap).then_some(Vec3::new(BOX_SIZE, BOX_SIZE, BOX_SIZE))
+    }
+
+    // 分離・整列・結合 (2Dと同じルール) 近傍は3Dのグリッドで探す
+    fn flock(&mut self) {
+        let p = &self.parameters;
+        let torus = self.torus();
+        let radius = p.separate_distance.max(p.alignment_distance).max(p.cohesion_distance);
+        self.grid.rebuild(self.agents.iter().map(|agent| agent.position), BOX_SIZE, radius, torus.is_some());
+        let (separate_sq, alignment_sq, cohesion_sq) = (
+            p.separate_distance.powi(2),
+            p.alignment_distance.powi(2),
+            p.cohesion_distance.powi(2),
+        );
+        let half = Vec3::new(BOX_SIZE / 2.0, BOX_SIZE / 2.0, BOX_SIZE / 2.0);
+
+        self.steering.clear();
+        self.steering.resize(self.agents.len(), Vec3::ZERO);
+        for (i, agent) in self.agents.iter().enumerate() {
+            let (mut separate, mut separate_count) = (Vec3::ZERO, 0);
+            let (mut alignment, mut alignment_count) = (Vec3::ZERO, 0);
+            let (mut cohesion, mut cohesion_count) = (Vec3::ZERO, 0);
+            for j in self.grid.neighbors(agent.position, radius) {
+                if i == j {
+                    continue;
+                }
+                let other = &self.agents[j];
+                // otherからagentへのベクトル
+                let away = offset(other.position, agent.position, torus);
+                let distance = away.length_sq();
+                // 分離
+                if distance > 0. && distance < separate_sq {
+                    separate += away;
+                    separate_count += 1;
+                }
+                // 整列
+                if distance > 1.0 && distance < alignment_sq {
+                    alignment += other.velocity;
+                    alignment_count += 1;
+                }
+                // 結合 (端をまたいだ相手も近い方の像の位置で平均する)
+                if distance > 0.5 && distance < cohesion_sq {
+                    cohesion += -away;
+                    cohesion_count += 1;
+                }
+            }
+
+            let mut steering = Vec3::ZERO;
+            if separate_count > 0 {
+                steering += steer(separate, agent.velocity, p.separate_force, p.max_speed, p);
+            }
+            if alignment_count > 0 {
+                steering += steer(alignment, agent.velocity, p.alignment_force, p.max_speed, p);
+            }
+            if cohesion_count > 0 {
+                // agentからみた近傍の重心へのベクトル
+                steering += steer(cohesion * (1.0 / cohesion_count as f64), agent.velocity, p.cohesion_force, p.max_speed, p);
+            }
+            if p.steering_model == SteeringModel::Reynolds {
+                steering = steering.limited(p.max_force * FORCE_UNIT);
+            }
+            steering += wall_avoidance(agent.position, agent.velocity, -half, half, p.max_speed, p);
+            self.steering[i] = steering;
+        }
+    }
+
+    // 箱の8つの角と12本の辺
+    fn draw_box(&self, renderer: &Renderer2d) {
+        let (width, height) = (self.width as f64, self.height as f64);
+        let half = BOX_SIZE / 2.0;
+        let corner = |i: usize| {
+            Vec3::new(
+                if i & 1 == 0 { -half } else { half },
+                if i & 2 == 0 { -half } else { half },
+                if i & 4 == 0 { -half } else { half },
+            )
+        };
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit != 0 {
+                    continue;
+                }
+                let a = self.camera.project(corner(i), width, height);
+                let b = self.camera.project(corner(i | bit), width, height);
+                if let (Some((a, _)), Some((b, _))) = (a, b) {
+                    renderer.line(a, b, 1.0, BOX_COLOR);
+                }
+            }
+        }
+    }
+}
+
+impl HasBoidParameters for Boid3d {
+    fn boid_parameters(&self) -> &BoidParameters {
+        &self.parameters
+    }
+
+    fn boid_parameters_mut(&mut self) -> &mut BoidParameters {
+        &mut self.parameters
+    }
+}
+
+#[async_trait(?Send)]
+impl Game for Boid3d {
+    async fn initialize(&self) -> Result<Box<dyn Game>> {
+        log!("Boid3d initialize: seed {}", self.parameters.seed);
+        let mut boid = Boid3d::with_parameters(self.width, self.height, self.parameters.clone());
+        boid.update_boid_count(self.parameters.boid_count);
+        Ok(Box::new(boid))
+    }
+
+    fn update(&mut self, dt: f64) {
+        self.flock();
+        let half = BOX_SIZE / 2.0;
+        let max_speed = self.parameters.max_speed;
+        let boundary_mode = self.parameters.boundary_mode;
+        let integrator = self.parameters.integrator;
+        for (agent, steering) in self.agents.iter_mut().zip(&self.steering) {
+            integrator.step(&mut agent.position, &mut agent.velocity, *steering, dt, |velocity| velocity.limited(max_speed));
+
+            // 箱の端
+            let axes = [
+                (&mut agent.position.x, &mut agent.velocity.x),
+                (&mut agent.position.y, &mut agent.velocity.y),
+                (&mut agent.position.z, &mut agent.velocity.z),
+            ];
+            for (position, velocity) in axes {
+                match boundary_mode {
+                    BoundaryMode::Wrap => *position = (*position + half).rem_euclid(BOX_SIZE) - half,
+                    BoundaryMode::Bounce | BoundaryMode::SoftWall => {
+                        let (p, v) = reflect(*position + half, *velocity, BOX_SIZE);
+                        (*position, *velocity) = (p - half, v);
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw(&self, renderer: &Renderer2d) {
+        renderer.clear();
+        self.draw_box(renderer);
+
+        // 奥から順に描く (手前のものが上に重なるように)
+        let (width, height) = (self.width as f64, self.height as f64);
+        let size = self.parameters.boid_size;
+        let mut triangles: Vec<(f64, [Point; 3])> = self
+            .agents
+            .iter()
+            .filter_map(|agent| {
+                let (center, scale) = self.camera.project(agent.position, width, height)?;
+                let depth = self.camera.to_view(agent.position).z;
+                // 進行方向の先を投影して画面上の向きと長さを決める (奥や手前を向くと短くなる)
+                let nose = agent.position + agent.velocity.scaled(size);
+                let (front, _) = self.camera.project(nose, width, height).unwrap_or((center, scale));
+                let angle = (front.y - center.y).atan2(front.x - center.x);
+                let length = ((front.x - center.x).powi(2) + (front.y - center.y).powi(2)).sqrt().max(size * scale * 0.3);
+                let back = size * scale * 0.6; // 2Dの三角形と同じ尖り具合
+                let corner = |angle: f64, length: f64| Point::new(center.x + length * angle.cos(), center.y + length * angle.sin());
+                Some((
+                    depth,
+                    [corner(angle, length), corner(angle + 2.0 * PI / 3.0, back), corner(angle - 2.0 * PI / 3.0, back)],
+                ))
+            })
+            .collect();
+        triangles.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        // 遠いものほど暗くする
+        let (near, far) = (self.camera.distance - BOX_SIZE, self.camera.distance + BOX_SIZE);
+        for (depth, triangle) in triangles {
+            let t = ((depth - near) / (far - near)).clamp(0.0, 1.0);
+            let color = format!("rgba(0, {}, 0, 0.9)", (255.0 - 175.0 * t) as u8);
+            renderer.triangle(
+                triangle[0].x, triangle[0].y, triangle[1].x, triangle[1].y, triangle[2].x, triangle[2].y,
+                &color,
+            );
+        }
+    }
+
+    // ドラッグでカメラを回す
+    fn handle_pointer(&mut self, event: &PointerEvent) {
+        match event.kind {
+            PointerKind::Down => self.drag = Some(event.position),
+            PointerKind::Move => {
+                if let Some(last) = self.drag {
+                    self.camera.orbit(event.position.x - last.x, event.position.y - last.y);
+                    self.drag = Some(event.position);
+                }
+            }
+            PointerKind::Up => self.drag = None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "boid3d"
+    }
+
+    fn parameters(&self) -> Vec<ParameterSpec> {
+        parameter::specs(&PARAMETERS)
+    }
+
+    fn parameter_values(&self) -> Vec<ParameterValue> {
+        parameter::values(&PARAMETERS, self)
+    }
+
+    fn parameter_defaults(&self) -> Vec<ParameterValue> {
+        parameter::values(&PARAMETERS, &Boid3d::new(self.width, self.height, self.parameters.seed))
+    }
+
+    fn handle_parameter(&mut self, change: &ParameterChange) {
+        parameter::apply(&PARAMETERS, self, change);
+    }
+
+    fn presets(&self) -> Vec<Preset> {
+        PRESETS.to_vec()
+    }
+
+    // 箱の大きさは変えずに、投影する画面だけ変える
+    fn on_resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+}
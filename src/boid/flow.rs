@@ -0,0 +1,111 @@
+// 時間で変わる流れの場 (パーリンノイズの値を向きにする)
+use std::f64::consts::TAU;
+
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+use crate::engine::{Point, Renderer2d};
+
+const ARROW_SPACING: f64 = 40.0; // 矢印を描く間隔 (px)
+const ARROW_LENGTH: f64 = 24.0;
+const ARROW_COLOR: &str = "rgba(0, 160, 255, 0.5)";
+
+pub struct FlowField {
+    permutation: Vec<u8>, // 0..=255を並べ替えたものを2回並べる (添字の折り返しを省くため)
+}
+
+impl FlowField {
+    // 同じシードなら同じ場になる (エージェントの乱数とは別に作る)
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut permutation: Vec<u8> = (0..=255).collect();
+        permutation.shuffle(&mut rng);
+        permutation.extend_from_within(..);
+        Self { permutation }
+    }
+
+    // positionでの流れの向き (単位ベクトル)
+    // scaleはノイズの1周期の大きさ (px)、phaseは時刻 × 変化の速さ
+    pub fn direction(&self, position: Point, scale: f64, phase: f64) -> Point {
+        let angle = self.noise(position.x / scale, position.y / scale, phase) * TAU;
+        Point::new(angle.cos(), angle.sin())
+    }
+
+    // 画面全体に矢印を並べて描く
+    pub fn draw(&self, renderer: &Renderer2d, width: f64, height: f64, scale: f64, phase: f64) {
+        let head = ARROW_LENGTH / 3.0;
+        let mut y = ARROW_SPACING / 2.0;
+        while y < height {
+            let mut x = ARROW_SPACING / 2.0;
+            while x < width {
+                let d = self.direction(Point::new(x, y), scale, phase);
+                let half = ARROW_LENGTH / 2.0;
+                let tail = Point::new(x - d.x * half, y - d.y * half);
+                let tip = Point::new(x + d.x * half, y + d.y * half);
+                renderer.line(tail, tip, 1.0, ARROW_COLOR);
+                // 矢じり (進む向きから±150度)
+                for side in [1.0, -1.0] {
+                    let (sin, cos) = (side * 150f64.to_radians()).sin_cos();
+                    let wing = Point::new(tip.x + (d.x * cos - d.y * sin) * head, tip.y + (d.x * sin + d.y * cos) * head);
+                    renderer.line(tip, wing, 1.0, ARROW_COLOR);
+                }
+                x += ARROW_SPACING;
+            }
+            y += ARROW_SPACING;
+        }
+    }
+
+    // 3次元のパーリンノイズ (だいたい-1..1)
+    fn noise(&self, x: f64, y: f64, z: f64) -> f64 {
+        let p = &self.permutation;
+        let (xi, yi, zi) = (cell(x), cell(y), cell(z));
+        let (x, y, z) = (x - x.floor(), y - y.floor(), z - z.floor());
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let a = p[xi] as usize + yi;
+        let (aa, ab) = (p[a] as usize + zi, p[a + 1] as usize + zi);
+        let b = p[xi + 1] as usize + yi;
+        let (ba, bb) = (p[b] as usize + zi, p[b + 1] as usize + zi);
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, grad(p[aa], x, y, z), grad(p[ba], x - 1.0, y, z)),
+                lerp(u, grad(p[ab], x, y - 1.0, z), grad(p[bb], x - 1.0, y - 1.0, z)),
+            ),
+            lerp(
+                v,
+                lerp(u, grad(p[aa + 1], x, y, z - 1.0), grad(p[ba + 1], x - 1.0, y, z - 1.0)),
+                lerp(u, grad(p[ab + 1], x, y - 1.0, z - 1.0), grad(p[bb + 1], x - 1.0, y - 1.0, z - 1.0)),
+            ),
+        )
+    }
+}
+
+// 格子の番号 (256で折り返す)
+fn cell(t: f64) -> usize {
+    (t.floor() as i64).rem_euclid(256) as usize
+}
+
+// 6t^5 - 15t^4 + 10t^3
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+// hashで決まる勾配ベクトルと(x, y, z)の内積
+fn grad(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
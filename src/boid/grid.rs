@@ -0,0 +1,193 @@
+// 近傍探索用の一様グリッド (2Dと3Dで共通)
+// 毎フレーム作り直して、近くのセルに入っているエージェントだけを調べる
+use std::ops::Range;
+
+use crate::engine::{Point, Vec3, Vector};
+
+const MAX_DIM: usize = 3;
+
+pub type SpatialGrid = Grid<Point>;
+pub type SpatialGrid3d = Grid<Vec3>;
+
+pub struct Grid<V> {
+    origin: V,               // 一番小さい角
+    cell: [f64; MAX_DIM],    // 軸ごとのセルの大きさ
+    counts: [usize; MAX_DIM], // 軸ごとのセルの数 (使わない軸は1)
+    wrap: bool, // trueなら端と反対側の端がつながっているものとして探す
+    cell_start: Vec<usize>, // セルcの中身は indices[cell_start[c]..cell_start[c + 1]]
+    indices: Vec<usize>,
+}
+
+impl<V: Vector> Default for Grid<V> {
+    fn default() -> Self {
+        Grid {
+            origin: V::ZERO,
+            cell: [1.0; MAX_DIM],
+            counts: [0; MAX_DIM],
+            wrap: false,
+            cell_start: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+}
+
+impl<V: Vector> Grid<V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 範囲外に少しはみ出した位置は端のセルに入れる
+    // wrapのときは反対側のセルとつなげるので、範囲をちょうど割り切る (cell_size以上の) 大きさのセルにする
+    fn build(&mut self, positions: impl Iterator<Item = V> + Clone, origin: V, size: V, cell_size: f64, wrap: bool) {
+        let cell_size = cell_size.max(1.0);
+        self.origin = origin;
+        self.wrap = wrap;
+        for axis in 0..MAX_DIM {
+            if axis >= V::DIM {
+                (self.counts[axis], self.cell[axis]) = (1, 1.0);
+                continue;
+            }
+            let length = size.axis(axis);
+            if wrap {
+                self.counts[axis] = ((length / cell_size).floor() as usize).max(1);
+                self.cell[axis] = (length / self.counts[axis] as f64).max(1.0);
+            } else {
+                self.counts[axis] = ((length / cell_size).ceil() as usize).max(1);
+                self.cell[axis] = cell_size;
+            }
+        }
+
+        // 計数ソート
+        let cells = self.counts.iter().product::<usize>();
+        self.cell_start.clear();
+        self.cell_start.resize(cells + 1, 0);
+        for position in positions.clone() {
+            let c = self.cell_of(position);
+            self.cell_start[c + 1] += 1;
+        }
+        for c in 0..cells {
+            self.cell_start[c + 1] += self.cell_start[c];
+        }
+        let mut next = self.cell_start.clone();
+        self.indices.clear();
+        self.indices.resize(self.cell_start[cells], 0);
+        for (i, position) in positions.enumerate() {
+            let c = self.cell_of(position);
+            self.indices[next[c]] = i;
+            next[c] += 1;
+        }
+    }
+
+    // centerから半径radius以内にいる可能性のあるエージェントの添字
+    // 実際の距離の判定は呼び出し側で行う
+    // まだrebuildしていないときは何も返さない
+    pub fn neighbors(&self, center: V, radius: f64) -> impl Iterator<Item = usize> + '_ {
+        self.neighbor_ranges(center, radius).flat_map(move |range| self.indices[range].iter().copied())
+    }
+
+    // neighborsと同じセルを、order()の中の範囲で返す
+    pub fn neighbor_ranges(&self, center: V, radius: f64) -> impl Iterator<Item = Range<usize>> + '_ {
+        let spans = [0, 1, 2].map(|axis| self.cell_span(center.axis(axis) - self.origin.axis(axis), radius, axis));
+        self.ranges(spans)
+    }
+
+    // セルcに入っている誰かから半径radius以内にいる可能性のあるセル (order()の中の範囲 c自身も入る)
+    pub fn cell_neighbor_ranges(&self, c: usize, radius: f64) -> impl Iterator<Item = Range<usize>> + '_ {
+        let mut rest = c;
+        let spans = [0, 1, 2].map(|axis| {
+            let i = rest % self.counts[axis];
+            rest /= self.counts[axis];
+            // セルの中はセルの端からradius以内 (radius / セルの大きさ を切り上げた数だけ両側のセル)
+            let reach = (radius / self.cell[axis]).ceil() as i64;
+            self.clamp_span(i as i64 - reach, i as i64 + reach, axis)
+        });
+        self.ranges(spans)
+    }
+
+    // セルの数 (rebuildする前は0)
+    pub fn cell_count(&self) -> usize {
+        self.cell_start.len().saturating_sub(1)
+    }
+
+    // セルcに入っているもの (order()の中の範囲)
+    pub fn cell(&self, c: usize) -> Range<usize> {
+        self.cell_start[c]..self.cell_start[c + 1]
+    }
+
+    // 軸ごとのセルの添字の範囲をorder()の中の範囲にする
+    fn ranges(&self, [(x0, x1), (y0, y1), (z0, z1)]: [(i64, i64); MAX_DIM]) -> impl Iterator<Item = Range<usize>> + '_ {
+        let [cols, rows, layers] = self.counts.map(|count| count as i64);
+        (z0..=z1).flat_map(move |cz| {
+            (y0..=y1).flat_map(move |cy| {
+                (x0..=x1).map(move |cx| {
+                    // wrapのときは範囲外の添字を反対側のセルに読み替える (そうでなければ範囲内なのでそのまま)
+                    let c = ((cz.rem_euclid(layers) * rows + cy.rem_euclid(rows)) * cols + cx.rem_euclid(cols)) as usize;
+                    self.cell(c)
+                })
+            })
+        })
+    }
+
+    // rebuildで渡した位置の添字をセルの順に並べたもの (同じセルのものが続く)
+    pub fn order(&self) -> &[usize] {
+        &self.indices
+    }
+
+    // 1軸ぶんの調べるセルの範囲 (centerはoriginからの距離)
+    fn cell_span(&self, center: f64, radius: f64, axis: usize) -> (i64, i64) {
+        let cell = self.cell[axis];
+        self.clamp_span(((center - radius) / cell).floor() as i64, ((center + radius) / cell).floor() as i64, axis)
+    }
+
+    // セルの添字の範囲firstからendを、範囲内に収める (wrapなら一周を超えないようにする)
+    fn clamp_span(&self, first: i64, end: i64, axis: usize) -> (i64, i64) {
+        let count = self.counts[axis];
+        if count == 0 {
+            return (1, 0);
+        }
+        if axis >= V::DIM {
+            return (0, 0);
+        }
+        let last = count as i64 - 1;
+        if !self.wrap {
+            (first.clamp(0, last), end.clamp(0, last))
+        } else if end - first >= last {
+            // 一周以上するなら全部のセルを1回ずつ
+            (0, last)
+        } else {
+            (first, end)
+        }
+    }
+
+    fn cell_of(&self, position: V) -> usize {
+        let mut c = 0;
+        for axis in (0..V::DIM).rev() {
+            let count = self.counts[axis];
+            let i = ((position.axis(axis) - self.origin.axis(axis)) / self.cell[axis]).floor().clamp(0.0, (count - 1) as f64) as usize;
+            c = c * count + i;
+        }
+        c
+    }
+}
+
+impl Grid<Point> {
+    // 0..width, 0..heightの画面
+    pub fn rebuild(
+        &mut self,
+        positions: impl Iterator<Item = Point> + Clone,
+        width: f64,
+        height: f64,
+        cell_size: f64,
+        wrap: bool,
+    ) {
+        self.build(positions, Point::ZERO, Point::new(width, height), cell_size, wrap);
+    }
+}
+
+impl Grid<Vec3> {
+    // 原点が中心で一辺sizeの箱
+    pub fn rebuild(&mut self, positions: impl Iterator<Item = Vec3> + Clone, size: f64, cell_size: f64, wrap: bool) {
+        let half = size / 2.0;
+        self.build(positions, Vec3::new(-half, -half, -half), Vec3::new(size, size, size), cell_size, wrap);
+    }
+}
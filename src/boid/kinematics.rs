@@ -0,0 +1,381 @@
+// エージェントの位置・速度・加速度を成分ごとのf32の配列で持つ
+// 同じ成分が並んでいるので、積分や画面端の処理のループはwasm32のsimd128で自動ベクトル化される
+// (simd128は対応していないブラウザで読み込めなくなるので、npm run build:simdのときだけ有効にする)
+// (分岐はselectになる形で書き、ループの中で他の配列やメソッドを呼ばない)
+use std::ops::Range;
+
+use crate::engine::Point;
+
+use super::boid::Integrator;
+use super::grid::SpatialGrid;
+
+#[derive(Debug, Clone, Default)]
+pub struct Kinematics {
+    pub x: Vec<f32>,
+    pub y: Vec<f32>,
+    pub vx: Vec<f32>, // px/s
+    pub vy: Vec<f32>,
+    pub ax: Vec<f32>, // px/s²
+    pub ay: Vec<f32>,
+}
+
+impl Kinematics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        for column in self.columns() {
+            column.clear();
+        }
+    }
+
+    pub fn push(&mut self, position: Point, velocity: Point) {
+        self.x.push(position.x as f32);
+        self.y.push(position.y as f32);
+        self.vx.push(velocity.x as f32);
+        self.vy.push(velocity.y as f32);
+        self.ax.push(0.0);
+        self.ay.push(0.0);
+    }
+
+    // 並びを保ったままi番目を消す
+    pub fn remove(&mut self, i: usize) {
+        for column in self.columns() {
+            column.remove(i);
+        }
+    }
+
+    // keep[i]がfalseのものを消す
+    pub fn retain(&mut self, keep: &[bool]) {
+        for column in self.columns() {
+            let mut i = 0;
+            column.retain(|_| {
+                i += 1;
+                keep[i - 1]
+            });
+        }
+    }
+
+    // i番目を置き直す (加速度は0にする)
+    pub fn set(&mut self, i: usize, position: Point, velocity: Point) {
+        self.set_position(i, position);
+        self.set_velocity(i, velocity);
+        self.ax[i] = 0.0;
+        self.ay[i] = 0.0;
+    }
+
+    pub fn position(&self, i: usize) -> Point {
+        Point::new(self.x[i] as f64, self.y[i] as f64)
+    }
+
+    pub fn velocity(&self, i: usize) -> Point {
+        Point::new(self.vx[i] as f64, self.vy[i] as f64)
+    }
+
+    pub fn set_position(&mut self, i: usize, position: Point) {
+        self.x[i] = position.x as f32;
+        self.y[i] = position.y as f32;
+    }
+
+    pub fn set_velocity(&mut self, i: usize, velocity: Point) {
+        self.vx[i] = velocity.x as f32;
+        self.vy[i] = velocity.y as f32;
+    }
+
+    pub fn positions(&self) -> impl Iterator<Item = Point> + Clone + '_ {
+        (0..self.len()).map(|i| self.position(i))
+    }
+
+    // dt秒ぶん進める 速度はspeed_limits (エージェントごとの最高速) で制限する
+    pub fn integrate(&mut self, integrator: Integrator, dt: f32, speed_limits: &[f32]) {
+        // 全部同じ長さに切っておくと境界チェックが消える
+        let n = self.len();
+        let (x, y) = (&mut self.x[..n], &mut self.y[..n]);
+        let (vx, vy) = (&mut self.vx[..n], &mut self.vy[..n]);
+        let (ax, ay) = (&self.ax[..n], &self.ay[..n]);
+        let speed_limits = &speed_limits[..n];
+        match integrator {
+            Integrator::Euler => {
+                for i in 0..n {
+                    x[i] += vx[i] * dt;
+                    y[i] += vy[i] * dt;
+                    (vx[i], vy[i]) = limited(vx[i] + ax[i] * dt, vy[i] + ay[i] * dt, speed_limits[i]);
+                }
+            }
+            Integrator::SemiImplicitEuler => {
+                for i in 0..n {
+                    (vx[i], vy[i]) = limited(vx[i] + ax[i] * dt, vy[i] + ay[i] * dt, speed_limits[i]);
+                    x[i] += vx[i] * dt;
+                    y[i] += vy[i] * dt;
+                }
+            }
+        }
+    }
+
+    // 反対側から出てくるようにする
+    pub fn wrap(&mut self, width: f32, height: f32) {
+        for x in &mut self.x {
+            *x -= (*x / width).floor() * width;
+        }
+        for y in &mut self.y {
+            *y -= (*y / height).floor() * height;
+        }
+    }
+
+    // 0..width, 0..heightの外に出ていたら内側に折り返して、速度を内向きにする
+    pub fn reflect(&mut self, width: f32, height: f32) {
+        reflect(&mut self.x, &mut self.vx, width);
+        reflect(&mut self.y, &mut self.vy, height);
+    }
+
+    fn columns(&mut self) -> [&mut Vec<f32>; 6] {
+        [&mut self.x, &mut self.y, &mut self.vx, &mut self.vy, &mut self.ax, &mut self.ay]
+    }
+}
+
+// 近傍の和を取るための列
+// グリッドのセルの順に並べ直して、同じセルのエージェントが続くようにする
+// 1つの近傍jに対してセルの全員iをまとめて計算する (iのループは和を取らずに列へ足し込むだけなので自動ベクトル化できる)
+#[derive(Debug, Clone, Default)]
+pub struct NeighborColumns {
+    agents: AgentColumns,
+    totals: [Vec<f32>; SUMS], // 近傍の和 (並びはNeighborSumsと同じ)
+}
+
+// セルの順に並べ直したエージェント
+#[derive(Debug, Clone, Default)]
+struct AgentColumns {
+    x: Vec<f32>,
+    y: Vec<f32>,
+    vx: Vec<f32>,
+    vy: Vec<f32>,
+    prey: Vec<f32>,        // 被食者なら1
+    hx: Vec<f32>,          // 進行方向の単位ベクトル (止まっているときは0)
+    hy: Vec<f32>,
+    all_around: Vec<f32>,  // 止まっていて全方向が見えるなら1
+}
+
+// 近傍を数える条件 (全員で共通)
+#[derive(Debug, Clone, Copy)]
+pub struct NeighborRules {
+    pub torus: Option<(f32, f32)>, // 画面端がつながっているときの (幅, 高さ)
+    pub radius_sq: f32,
+    pub separate_sq: f32,
+    pub alignment_sq: f32,
+    pub cohesion_sq: f32,
+    pub panic_sq: f32,
+    pub separate_cos: f32, // 視野の半分の角度のcos (全方向が見えるときは-1より小さくする)
+    pub alignment_cos: f32,
+    pub cohesion_cos: f32,
+}
+
+// 近傍の和 (ベクトルはagentから見た向き 分離と逃避は相手からagentへ、結合はagentから相手へ)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NeighborSums {
+    pub neighbors: f32, // radius内にいる数 (自分も入る)
+    pub separate: (f32, f32),
+    pub separate_count: f32,
+    pub alignment: (f32, f32),
+    pub alignment_count: f32,
+    pub cohesion: (f32, f32),
+    pub cohesion_count: f32,
+    pub flee: (f32, f32),
+    pub flee_count: f32,
+}
+
+const SUMS: usize = 13; // NeighborSumsの成分の数
+
+impl NeighborColumns {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // kinematicsをgridのセルの順に並べ直して、近傍の和を取る (preyはi番目が被食者か)
+    // 結果はsums(k)で、grid.order()[k]番目のエージェントのものを読む
+    pub fn sum(&mut self, kinematics: &Kinematics, grid: &SpatialGrid, radius: f64, rules: &NeighborRules, prey: impl Fn(usize) -> bool) {
+        self.gather(kinematics, grid.order(), prey);
+        for c in 0..grid.cell_count() {
+            let cell = grid.cell(c);
+            if cell.is_empty() {
+                continue;
+            }
+            for other in grid.cell_neighbor_ranges(c, radius) {
+                self.add_neighbors(cell.clone(), other, rules);
+            }
+        }
+    }
+
+    pub fn sums(&self, k: usize) -> NeighborSums {
+        let t = |s: usize| self.totals[s][k];
+        NeighborSums {
+            neighbors: t(0),
+            separate: (t(1), t(2)),
+            separate_count: t(3),
+            alignment: (t(4), t(5)),
+            alignment_count: t(6),
+            cohesion: (t(7), t(8)),
+            cohesion_count: t(9),
+            flee: (t(10), t(11)),
+            flee_count: t(12),
+        }
+    }
+
+    fn gather(&mut self, kinematics: &Kinematics, order: &[usize], prey: impl Fn(usize) -> bool) {
+        let n = order.len();
+        let agents = &mut self.agents;
+        for column in [&mut agents.x, &mut agents.y, &mut agents.vx, &mut agents.vy, &mut agents.prey, &mut agents.hx, &mut agents.hy, &mut agents.all_around] {
+            column.clear();
+        }
+        for &i in order {
+            let (vx, vy) = (kinematics.vx[i], kinematics.vy[i]);
+            let speed = (vx * vx + vy * vy).sqrt();
+            agents.x.push(kinematics.x[i]);
+            agents.y.push(kinematics.y[i]);
+            agents.vx.push(vx);
+            agents.vy.push(vy);
+            agents.prey.push(if prey(i) { 1.0 } else { 0.0 });
+            agents.hx.push(if speed > 0.0 { vx / speed } else { 0.0 });
+            agents.hy.push(if speed > 0.0 { vy / speed } else { 0.0 });
+            agents.all_around.push(if speed > 0.0 { 0.0 } else { 1.0 });
+        }
+        for total in self.totals.iter_mut() {
+            total.clear();
+            total.resize(n, 0.0);
+        }
+    }
+
+    // cellの全員に、otherの全員を近傍として足す
+    fn add_neighbors(&mut self, cell: Range<usize>, other: Range<usize>, rules: &NeighborRules) {
+        let [neighbors, sx, sy, sn, ax, ay, an, cx, cy, cn, fx, fy, fn_] = self.totals.each_mut().map(|total| &mut total[cell.clone()]);
+        let (agents, others) = (self.agents.slice(cell), self.agents.slice(other));
+        add_neighbors(&agents, &others, rules, neighbors, sx, sy, sn, ax, ay, an, cx, cy, cn, fx, fy, fn_);
+    }
+}
+
+impl AgentColumns {
+    fn slice(&self, range: Range<usize>) -> Columns<'_> {
+        Columns {
+            x: &self.x[range.clone()],
+            y: &self.y[range.clone()],
+            vx: &self.vx[range.clone()],
+            vy: &self.vy[range.clone()],
+            prey: &self.prey[range.clone()],
+            hx: &self.hx[range.clone()],
+            hy: &self.hy[range.clone()],
+            all_around: &self.all_around[range],
+        }
+    }
+}
+
+// NeighborColumnsの一部
+struct Columns<'a> {
+    x: &'a [f32],
+    y: &'a [f32],
+    vx: &'a [f32],
+    vy: &'a [f32],
+    prey: &'a [f32],
+    hx: &'a [f32],
+    hy: &'a [f32],
+    all_around: &'a [f32],
+}
+
+// agentsの全員に、othersの全員を近傍として足す
+// 条件は0か1のf32にして掛け合わせる (分岐にしない)
+// 和の列は別々の引数で受け取る (1つの配列などにまとめると重なっていないことがコンパイラに分からず、ベクトル化されない)
+#[inline(never)]
+#[allow(clippy::too_many_arguments)]
+fn add_neighbors(
+    agents: &Columns,
+    others: &Columns,
+    rules: &NeighborRules,
+    neighbors: &mut [f32],
+    sx: &mut [f32],
+    sy: &mut [f32],
+    sn: &mut [f32],
+    ax: &mut [f32],
+    ay: &mut [f32],
+    an: &mut [f32],
+    cx: &mut [f32],
+    cy: &mut [f32],
+    cn: &mut [f32],
+    fx: &mut [f32],
+    fy: &mut [f32],
+    fn_: &mut [f32],
+) {
+    // 全部同じ長さに切っておくと境界チェックが消える
+    let n = agents.x.len();
+    let (x, y, prey, hx, hy, all_around) =
+        (agents.x, &agents.y[..n], &agents.prey[..n], &agents.hx[..n], &agents.hy[..n], &agents.all_around[..n]);
+    let neighbors = &mut neighbors[..n];
+    let (sx, sy, sn) = (&mut sx[..n], &mut sy[..n], &mut sn[..n]);
+    let (ax, ay, an) = (&mut ax[..n], &mut ay[..n], &mut an[..n]);
+    let (cx, cy, cn) = (&mut cx[..n], &mut cy[..n], &mut cn[..n]);
+    let (fx, fy, fn_) = (&mut fx[..n], &mut fy[..n], &mut fn_[..n]);
+    let (width, height) = rules.torus.unwrap_or((f32::INFINITY, f32::INFINITY));
+    let wrap = |d: f32, size: f32| if d > size / 2.0 { d - size } else if d < -size / 2.0 { d + size } else { d };
+    let one = |condition: bool| if condition { 1.0f32 } else { 0.0 };
+    for j in 0..others.x.len() {
+        let (xj, yj, vxj, vyj, prey_j) = (others.x[j], others.y[j], others.vx[j], others.vy[j], others.prey[j]);
+        for i in 0..n {
+            // jからiへのベクトル (端がつながっていれば近い方の像から)
+            let dx = wrap(x[i] - xj, width);
+            let dy = wrap(y[i] - yj, height);
+            let distance = dx * dx + dy * dy;
+            let root = distance.sqrt();
+            // 進行方向とjの方向のなす角のcosがview_cos以上なら見える (割り算の代わりにrootを掛けて比べる)
+            let front = -(hx[i] * dx + hy[i] * dy);
+            let visible = |cos: f32| one(front >= cos * root).max(all_around[i]);
+            let same = 1.0 - (prey[i] - prey_j).abs();
+            // 捕食者どうしは分離だけして、ばらけて狩りをする
+            let flocking = same * prey[i];
+            let separate = same * one(distance > 0.0) * one(distance < rules.separate_sq) * visible(rules.separate_cos);
+            let alignment = flocking * one(distance > 1.0) * one(distance < rules.alignment_sq) * visible(rules.alignment_cos);
+            let cohesion = flocking * one(distance > 0.5) * one(distance < rules.cohesion_sq) * visible(rules.cohesion_cos);
+            // 逃避 (視野に関係なく気づく) 近い捕食者ほど強く避ける
+            let flee = prey[i] * (1.0 - same) * one(distance > 0.0) * one(distance < rules.panic_sq);
+            let inverse = flee / distance.max(f32::MIN_POSITIVE);
+            neighbors[i] += one(distance < rules.radius_sq);
+            sx[i] += separate * dx;
+            sy[i] += separate * dy;
+            sn[i] += separate;
+            ax[i] += alignment * vxj;
+            ay[i] += alignment * vyj;
+            an[i] += alignment;
+            cx[i] -= cohesion * dx;
+            cy[i] -= cohesion * dy;
+            cn[i] += cohesion;
+            fx[i] += inverse * dx;
+            fy[i] += inverse * dy;
+            fn_[i] += flee;
+        }
+    }
+}
+
+// 長さがmaxを超えていたらmaxに縮める
+#[inline(always)]
+fn limited(vx: f32, vy: f32, max: f32) -> (f32, f32) {
+    let speed_sq = vx * vx + vy * vy;
+    let scale = if speed_sq > max * max { max / speed_sq.sqrt() } else { 1.0 };
+    (vx * scale, vy * scale)
+}
+
+fn reflect(position: &mut [f32], velocity: &mut [f32], limit: f32) {
+    let n = position.len();
+    let velocity = &mut velocity[..n];
+    for i in 0..n {
+        let (p, v) = (position[i], velocity[i]);
+        let below = p < 0.0;
+        let above = p > limit;
+        position[i] = if below { (-p).min(limit) } else if above { (2.0 * limit - p).max(0.0) } else { p };
+        velocity[i] = if below { v.abs() } else if above { -v.abs() } else { v };
+    }
+}
@@ -1 +1,8 @@
-pub mod boid;
\ No newline at end of file
+pub mod analytics;
+pub mod boid;
+pub mod boid3d;
+pub mod flow;
+pub mod grid;
+pub mod kinematics;
+pub mod obstacle;
+pub mod trajectory;
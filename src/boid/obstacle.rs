@@ -0,0 +1,167 @@
+// ボイドの世界に置く静的な障害物 (円と多角形)
+use crate::engine::{Point, Renderer2d};
+
+const OBSTACLE_COLOR: &str = "rgba(128, 128, 128, 0.8)";
+const CONTACT_MARGIN: f64 = 0.5; // 衝突したときに表面から離しておく距離
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Obstacle {
+    Circle { center: Point, radius: f64 },
+    Polygon { vertices: Vec<Point> },
+}
+
+impl Obstacle {
+    pub fn contains(&self, p: Point) -> bool {
+        match self {
+            Obstacle::Circle { center, radius } => distance_sq(p, *center) < radius.powi(2),
+            Obstacle::Polygon { vertices } => {
+                // 半直線との交差回数で判定
+                let mut inside = false;
+                for (a, b) in edges(vertices) {
+                    if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+        }
+    }
+
+    // 表面上でpに一番近い点
+    pub fn closest_point(&self, p: Point) -> Point {
+        match self {
+            Obstacle::Circle { center, radius } => {
+                let d = distance_sq(p, *center).sqrt();
+                if d == 0.0 {
+                    Point::new(center.x + radius, center.y)
+                } else {
+                    Point::new(center.x + (p.x - center.x) / d * radius, center.y + (p.y - center.y) / d * radius)
+                }
+            }
+            Obstacle::Polygon { vertices } => edges(vertices)
+                .map(|(a, b)| closest_on_segment(p, a, b))
+                .min_by(|a, b| distance_sq(p, *a).total_cmp(&distance_sq(p, *b)))
+                .unwrap_or(p),
+        }
+    }
+
+    // pから見た表面までの距離と、表面から外向きの単位法線
+    pub fn surface(&self, p: Point) -> (f64, Point) {
+        let closest = self.closest_point(p);
+        let d = distance_sq(p, closest).sqrt();
+        let sign = if self.contains(p) { -1.0 } else { 1.0 };
+        let normal = if d == 0.0 {
+            match self {
+                Obstacle::Circle { center, .. } => unit(Point::new(p.x - center.x, p.y - center.y)),
+                Obstacle::Polygon { .. } => Point::new(0.0, 0.0),
+            }
+        } else {
+            Point::new((p.x - closest.x) / d * sign, (p.y - closest.y) / d * sign)
+        };
+        (d * sign, normal)
+    }
+
+    // fromからtoへ動いたときに障害物に入るなら、入る直前の位置と外向きの法線を返す
+    // 1フレームの移動が障害物より大きくてもすり抜けないように線分で判定する
+    pub fn collide(&self, from: Point, to: Point) -> Option<(Point, Point)> {
+        if self.contains(from) {
+            return self.contains(to).then(|| self.push_out(to));
+        }
+        let d = Point::new(to.x - from.x, to.y - from.y);
+        let t = match self {
+            Obstacle::Circle { center, radius } => {
+                let f = Point::new(from.x - center.x, from.y - center.y);
+                let a = d.x * d.x + d.y * d.y;
+                let b = 2.0 * (f.x * d.x + f.y * d.y);
+                let c = f.x * f.x + f.y * f.y - radius * radius;
+                let discriminant = b * b - 4.0 * a * c;
+                if a == 0.0 || discriminant < 0.0 {
+                    None
+                } else {
+                    let t = (-b - discriminant.sqrt()) / (2.0 * a);
+                    (0.0..=1.0).contains(&t).then_some(t)
+                }
+            }
+            Obstacle::Polygon { vertices } => edges(vertices)
+                .filter_map(|(a, b)| segment_intersection(from, to, a, b))
+                .min_by(|a, b| a.total_cmp(b)),
+        };
+
+        match t {
+            Some(t) => {
+                let contact = Point::new(from.x + d.x * t, from.y + d.y * t);
+                let (_, normal) = self.surface(Point::new(from.x + d.x * t * 0.5, from.y + d.y * t * 0.5));
+                Some((Point::new(contact.x + normal.x * CONTACT_MARGIN, contact.y + normal.y * CONTACT_MARGIN), normal))
+            }
+            // 誤差で交点が見つからなかったのに終点が中にあるときは外に押し出す
+            None if self.contains(to) => Some(self.push_out(to)),
+            None => None,
+        }
+    }
+
+    // 中にいる点を一番近い表面の外側へ出す
+    pub fn push_out(&self, p: Point) -> (Point, Point) {
+        let closest = self.closest_point(p);
+        let (_, normal) = self.surface(p);
+        (Point::new(closest.x + normal.x * CONTACT_MARGIN, closest.y + normal.y * CONTACT_MARGIN), normal)
+    }
+
+    // 画面の大きさが変わったときに位置を伸び縮みさせる (円は半径を変えずに中心だけ動かす)
+    pub fn rescale(&mut self, sx: f64, sy: f64) {
+        match self {
+            Obstacle::Circle { center, .. } => *center = Point::new(center.x * sx, center.y * sy),
+            Obstacle::Polygon { vertices } => {
+                for vertex in vertices.iter_mut() {
+                    *vertex = Point::new(vertex.x * sx, vertex.y * sy);
+                }
+            }
+        }
+    }
+
+    pub fn draw(&self, renderer: &Renderer2d) {
+        match self {
+            Obstacle::Circle { center, radius } => renderer.circle(*center, *radius, OBSTACLE_COLOR),
+            Obstacle::Polygon { vertices } => renderer.polygon(vertices, OBSTACLE_COLOR),
+        }
+    }
+}
+
+fn edges(vertices: &[Point]) -> impl Iterator<Item = (Point, Point)> + '_ {
+    vertices.iter().zip(vertices.iter().cycle().skip(1)).map(|(a, b)| (*a, *b))
+}
+
+fn distance_sq(a: Point, b: Point) -> f64 {
+    (a.x - b.x).powi(2) + (a.y - b.y).powi(2)
+}
+
+fn unit(v: Point) -> Point {
+    let norm = (v.x.powi(2) + v.y.powi(2)).sqrt();
+    if norm == 0.0 {
+        Point::new(1.0, 0.0)
+    } else {
+        Point::new(v.x / norm, v.y / norm)
+    }
+}
+
+fn closest_on_segment(p: Point, a: Point, b: Point) -> Point {
+    let ab = Point::new(b.x - a.x, b.y - a.y);
+    let length_sq = ab.x * ab.x + ab.y * ab.y;
+    if length_sq == 0.0 {
+        return a;
+    }
+    let t = (((p.x - a.x) * ab.x + (p.y - a.y) * ab.y) / length_sq).clamp(0.0, 1.0);
+    Point::new(a.x + ab.x * t, a.y + ab.y * t)
+}
+
+// 線分p-qと線分a-bが交わるなら、p-q上の位置 (0..1) を返す
+fn segment_intersection(p: Point, q: Point, a: Point, b: Point) -> Option<f64> {
+    let r = Point::new(q.x - p.x, q.y - p.y);
+    let s = Point::new(b.x - a.x, b.y - a.y);
+    let denominator = r.x * s.y - r.y * s.x;
+    if denominator == 0.0 {
+        return None;
+    }
+    let t = ((a.x - p.x) * s.y - (a.y - p.y) * s.x) / denominator;
+    let u = ((a.x - p.x) * r.y - (a.y - p.y) * r.x) / denominator;
+    ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then_some(t)
+}
@@ -0,0 +1,202 @@
+// 各エージェントの位置と速度の記録 (オフラインの解析用)
+// ブラウザに依存しないのでネイティブでも使える
+use crate::engine::{Game, Point};
+
+use super::boid::{Boid, BoidParameters, Species};
+
+const MAX_STATES: usize = 1_000_000; // 記録するエージェントの状態の上限 (これを超えたら記録をやめる)
+
+// 1エージェントぶんの状態
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AgentState {
+    pub id: usize, // エージェントの番号 (途中で他のエージェントが消えても変わらない)
+    pub species: Species,
+    pub position: Point, // px
+    pub velocity: Point, // px/s
+}
+
+// ある時刻の全エージェントの状態 (並びはその時点のもの 同じエージェントはidで追う)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub time: f64,
+    pub agents: Vec<AgentState>,
+}
+
+// 記録と、記録を始めたときのパラメータ (シードを含む)
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    pub width: u32,
+    pub height: u32,
+    pub parameters: BoidParameters,
+    pub frames: Vec<Frame>,
+    states: usize,
+}
+
+impl Trajectory {
+    pub fn new(width: u32, height: u32, parameters: BoidParameters) -> Self {
+        Self {
+            width,
+            height,
+            parameters,
+            frames: Vec::new(),
+            states: 0,
+        }
+    }
+
+    // 上限に達していて追加できなければfalse
+    pub fn push(&mut self, frame: Frame) -> bool {
+        if self.states + frame.agents.len() > MAX_STATES {
+            return false;
+        }
+        self.states += frame.agents.len();
+        self.frames.push(frame);
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // 先頭に "# 名前=値" の行でパラメータを書き、そのあとに1行1エージェントで書く
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+        for (name, value) in self.header() {
+            csv.push_str(&format!("# {}={}\n", name, value.trim_matches('"')));
+        }
+        csv.push_str("time,agent,species,x,y,vx,vy\n");
+        for frame in &self.frames {
+            for agent in &frame.agents {
+                csv.push_str(&format!(
+                    "{:.4},{},{},{:.3},{:.3},{:.3},{:.3}\n",
+                    frame.time,
+                    agent.id,
+                    species_name(agent.species),
+                    agent.position.x,
+                    agent.position.y,
+                    agent.velocity.x,
+                    agent.velocity.y
+                ));
+            }
+        }
+        csv
+    }
+
+    // {"width", "height", "seed", "parameters": {...}, "frames": [{"time", "agents": [{"id", "species", "x", "y", "vx", "vy"}]}]}
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{");
+        json.push_str(&format!("\"width\":{},\"height\":{},\"seed\":{},", self.width, self.height, self.parameters.seed));
+        let parameters: Vec<String> = parameter_entries(&self.parameters)
+            .into_iter()
+            .map(|(name, value)| format!("\"{}\":{}", name, value))
+            .collect();
+        json.push_str(&format!("\"parameters\":{{{}}},", parameters.join(",")));
+        json.push_str("\"frames\":[");
+        for (f, frame) in self.frames.iter().enumerate() {
+            if f > 0 {
+                json.push(',');
+            }
+            json.push_str(&format!("{{\"time\":{},\"agents\":[", number(frame.time)));
+            for (i, agent) in frame.agents.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                json.push_str(&format!(
+                    "{{\"id\":{},\"species\":\"{}\",\"x\":{},\"y\":{},\"vx\":{},\"vy\":{}}}",
+                    agent.id,
+                    species_name(agent.species),
+                    number(agent.position.x),
+                    number(agent.position.y),
+                    number(agent.velocity.x),
+                    number(agent.velocity.y)
+                ));
+            }
+            json.push_str("]}");
+        }
+        json.push_str("]}");
+        json
+    }
+
+    fn header(&self) -> Vec<(&'static str, String)> {
+        let mut header = vec![("width", self.width.to_string()), ("height", self.height.to_string())];
+        header.extend(parameter_entries(&self.parameters));
+        header
+    }
+}
+
+// 画面なしでstepsステップ (1ステップdt秒) 動かし、record_intervalごとの記録を返す
+// ページで記録したものと同じ形式になる
+pub fn run_headless(width: u32, height: u32, parameters: BoidParameters, steps: usize, dt: f64) -> Trajectory {
+    let mut boid = Boid::with_parameters(width, height, parameters);
+    boid.spawn_agents();
+    boid.start_recording();
+    for _ in 0..steps {
+        boid.update(dt);
+    }
+    boid.take_trajectory()
+}
+
+fn species_name(species: Species) -> &'static str {
+    match species {
+        Species::Prey => "prey",
+        Species::Predator => "predator",
+    }
+}
+
+// JSONの値として書く (NaNや無限大はnull)
+fn number(value: f64) -> String {
+    if value.is_finite() { value.to_string() } else { "null".to_string() }
+}
+
+fn text(value: impl std::fmt::Debug) -> String {
+    format!("\"{:?}\"", value)
+}
+
+// パラメータの名前とJSONの値
+fn parameter_entries(p: &BoidParameters) -> Vec<(&'static str, String)> {
+    let optional = |angle: Option<f64>| angle.map_or("null".to_string(), number);
+    vec![
+        ("seed", p.seed.to_string()),
+        ("boid_size", number(p.boid_size)),
+        ("boid_count", p.boid_count.to_string()),
+        ("max_speed", number(p.max_speed)),
+        ("max_force", number(p.max_force)),
+        ("integrator", text(p.integrator)),
+        ("steering_model", text(p.steering_model)),
+        ("separate_force", number(p.separate_force)),
+        ("separate_distance", number(p.separate_distance)),
+        ("alignment_force", number(p.alignment_force)),
+        ("alignment_distance", number(p.alignment_distance)),
+        ("cohesion_force", number(p.cohesion_force)),
+        ("cohesion_distance", number(p.cohesion_distance)),
+        ("view_angle", number(p.view_angle)),
+        ("separate_view_angle", optional(p.separate_view_angle)),
+        ("alignment_view_angle", optional(p.alignment_view_angle)),
+        ("cohesion_view_angle", optional(p.cohesion_view_angle)),
+        ("avoidance_force", number(p.avoidance_force)),
+        ("look_ahead", number(p.look_ahead)),
+        ("obstacle_radius", number(p.obstacle_radius)),
+        ("predator_count", p.predator_count.to_string()),
+        ("predator_size", number(p.predator_size)),
+        ("predator_max_speed", number(p.predator_max_speed)),
+        ("chase_force", number(p.chase_force)),
+        ("flee_force", number(p.flee_force)),
+        ("panic_distance", number(p.panic_distance)),
+        ("catch_distance", number(p.catch_distance)),
+        ("respawn_prey", p.respawn_prey.to_string()),
+        ("boundary_mode", text(p.boundary_mode)),
+        ("wall_margin", number(p.wall_margin)),
+        ("wall_force", number(p.wall_force)),
+        ("pointer_tool", text(p.pointer_tool)),
+        ("attractor_radius", number(p.attractor_radius)),
+        ("attractor_strength", number(p.attractor_strength)),
+        ("wind_force", number(p.wind_force)),
+        ("wind_direction", number(p.wind_direction)),
+        ("flow_force", number(p.flow_force)),
+        ("flow_scale", number(p.flow_scale)),
+        ("flow_speed", number(p.flow_speed)),
+        ("goal_force", number(p.goal_force)),
+        ("waypoint_radius", number(p.waypoint_radius)),
+        ("cluster_distance", number(p.cluster_distance)),
+        ("record_interval", number(p.record_interval)),
+    ]
+}
@@ -2,6 +2,7 @@
 use anyhow::{anyhow, Result};
 use std::{
     future::Future,
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use wasm_bindgen::{
@@ -9,13 +10,20 @@ use wasm_bindgen::{
     closure::{WasmClosure,WasmClosureFnOnce,},
 };
 use web_sys::{
-    CanvasRenderingContext2d, Document, HtmlCanvasElement, HtmlElement, Window, HtmlInputElement
+    CanvasRenderingContext2d, Document, Element, HtmlCanvasElement, HtmlElement, Window, HtmlInputElement,
+    HtmlSelectElement, Storage,
 };
 
+use crate::parameter::{self, ParameterSpec, ParameterValue, Preset};
+
+// wasm以外 (ネイティブのテストやベンチマーク) では標準出力に出す
 macro_rules! log {
-    ( $($t:tt)* ) => {
+    ( $($t:tt)* ) => {{
+        #[cfg(target_arch = "wasm32")]
         web_sys::console::log_1(&format!( $($t)* ).into());
-    }
+        #[cfg(not(target_arch = "wasm32"))]
+        println!( $($t)* );
+    }}
 }
 
 pub fn window() -> Result<Window> {
@@ -46,6 +54,13 @@ pub fn canvas() -> Result<HtmlCanvasElement>{
 }
 
 // キャンバスサイズ関連
+// set_canvas_fullscreenで画面いっぱいにしたか (ウィンドウの大きさが変わったら合わせ直す)
+static CANVAS_FULLSCREEN: AtomicBool = AtomicBool::new(false);
+
+pub fn is_canvas_fullscreen() -> bool {
+    CANVAS_FULLSCREEN.load(Ordering::Relaxed)
+}
+
 pub fn set_canvas_fullscreen() -> Result<(u32, u32)> {
     let window = window()?;
     let width = window
@@ -63,6 +78,7 @@ pub fn set_canvas_fullscreen() -> Result<(u32, u32)> {
     let canvas = canvas()?;
     canvas.set_width(width as u32);
     canvas.set_height(height as u32);
+    CANVAS_FULLSCREEN.store(true, Ordering::Relaxed);
 
     Ok((width as u32, height as u32))
 }
@@ -72,6 +88,7 @@ pub fn set_canvas_left_top(width: u32, height: u32) -> Result<(u32, u32)> {
     let canvas = canvas()?;
     canvas.set_width(width);
     canvas.set_height(height);
+    CANVAS_FULLSCREEN.store(false, Ordering::Relaxed);
     let html_canvas = canvas
         .dyn_into::<HtmlElement>()
         .map_err(|err: HtmlCanvasElement| anyhow!("Failed to convert canvas to HtmlElement: {:#?}", err))?;
@@ -98,6 +115,18 @@ pub fn request_animation_frame(callback: &LoopClosure) -> Result<i32>{
         .map_err(|err| anyhow!("Failed to request animation frame {:#?}", err))
 }
 
+// msミリ秒後に1回呼ぶ 返り値はclear_timeoutに渡すID
+pub fn set_timeout(callback: &Closure<dyn FnMut()>, ms: i32) -> Result<i32> {
+    window()?
+        .set_timeout_with_callback_and_timeout_and_arguments_0(callback.as_ref().unchecked_ref(), ms)
+        .map_err(|err| anyhow!("Failed to set timeout {:#?}", err))
+}
+
+pub fn clear_timeout(id: i32) -> Result<()> {
+    window()?.clear_timeout_with_handle(id);
+    Ok(())
+}
+
 pub fn closure_once<F, A, R>(fn_once: F) -> Closure<F::FnMut>
 where
     F: 'static + WasmClosureFnOnce<A, R>,
@@ -145,7 +174,296 @@ pub fn set_fps(fps: f64) -> Result<()> {
     Ok(())
 }
 
-pub fn set_parameter_ui(name: &str) -> Result<()> {
+// ゲームが宣言したパラメータからスライダーと数値入力を作る
+// #parameter-listがあればその中に、無ければ#parameterの末尾に並べる
+pub fn build_parameter_panel(parameters: &[ParameterSpec], values: &[ParameterValue]) -> Result<()> {
+    let document = document()?;
+    let container = document
+        .get_element_by_id("parameter-list")
+        .or_else(|| document.get_element_by_id("parameter"))
+        .ok_or_else(|| anyhow!("No element found with id 'parameter'"))?;
+    if container.id() == "parameter-list" {
+        container.set_inner_html("");
+    }
+
+    for (spec, value) in parameters.iter().zip(values) {
+        let group = create_element(&document, "div")?;
+        group.set_class_name("param-group");
+
+        let label = create_element(&document, "label")?;
+        set_attribute(&label, "for", &format!("param-{}", spec.name))?;
+        label.set_text_content(Some(&spec.display_label()));
+        group.append_child(&label).map_err(|err| anyhow!("Failed to append label: {:#?}", err))?;
+
+        for (kind, id) in [("range", "slider"), ("number", "input")] {
+            let input = create_element(&document, "input")?;
+            set_attribute(&input, "type", kind)?;
+            set_attribute(&input, "id", &format!("param-{}-{}", id, spec.name))?;
+            set_attribute(&input, "min", &spec.min.to_string())?;
+            set_attribute(&input, "max", &spec.max.to_string())?;
+            set_attribute(&input, "step", &spec.step.to_string())?;
+            set_attribute(&input, "value", &value.as_f64().to_string())?;
+            group.append_child(&input).map_err(|err| anyhow!("Failed to append input: {:#?}", err))?;
+        }
+
+        container.append_child(&group).map_err(|err| anyhow!("Failed to append parameter group: {:#?}", err))?;
+    }
+
+    Ok(())
+}
+
+// パネルの今の値 (name=value&...) inputの文字列をそのまま使う
+pub fn parameter_query(parameters: &[ParameterSpec]) -> Result<String> {
+    let values = parameters
+        .iter()
+        .map(|spec| Ok((spec.name, input_element(&format!("param-slider-{}", spec.name))?.value())))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(parameter::encode_query(values.into_iter()))
+}
+
+// queryの値をパネルに入れ、inputイベントを起こしてゲームにも伝える
+// パネルに無い名前は無視する
+pub fn apply_parameter_query(parameters: &[ParameterSpec], query: &str) -> Result<()> {
+    for (name, value) in parameter::decode_query(query) {
+        if !parameters.iter().any(|spec| spec.name == name) {
+            continue;
+        }
+        let slider = input_element(&format!("param-slider-{}", name))?;
+        slider.set_value(&value.to_string());
+        let event = web_sys::Event::new("input").map_err(|err| anyhow!("Failed to create input event: {:#?}", err))?;
+        slider
+            .dispatch_event(&event)
+            .map_err(|err| anyhow!("Failed to dispatch input event: {:#?}", err))?;
+    }
+    Ok(())
+}
+
+// URLのクエリ (シードもここに入っている)
+pub fn url_query() -> Result<String> {
+    window()?
+        .location()
+        .search()
+        .map_err(|err| anyhow!("Failed to get location.search: {:#?}", err))
+}
+
+// パネルの全部の値をURLのクエリに書く (履歴は増やさない)
+// ほかのクエリ (lifeのruleなど) はそのまま残す
+pub fn write_parameters_to_url(parameters: &[ParameterSpec]) -> Result<()> {
+    let window = window()?;
+    let href = window.location().href().map_err(|err| anyhow!("Failed to get location.href: {:#?}", err))?;
+    let url = web_sys::Url::new(&href).map_err(|err| anyhow!("Failed to parse url: {:#?}", err))?;
+    let search = url.search_params();
+    for spec in parameters {
+        search.set(spec.name, &input_element(&format!("param-slider-{}", spec.name))?.value());
+    }
+    url.set_search(&String::from(search.to_string()));
+    let path = format!("{}{}", url.pathname(), url.search());
+
+    // js側のpopstateと同じ形のstateにする
+    let state = web_sys::js_sys::Object::new();
+    web_sys::js_sys::Reflect::set(&state, &"path".into(), &path.as_str().into())
+        .map_err(|err| anyhow!("Failed to set history state: {:#?}", err))?;
+    window
+        .history()
+        .map_err(|err| anyhow!("Failed to get history: {:#?}", err))?
+        .replace_state_with_url(&state, "", Some(&path))
+        .map_err(|err| anyhow!("Failed to replace history state: {:#?}", err))
+}
+
+// プリセットの選択・読み込み・保存・削除のUIを#parameter-listの先頭に作る
+// ユーザーのプリセットはlocalStorageに "preset:{game}:{名前}" で、値はparameter_queryの形で保存する
+pub fn build_preset_ui(game: &'static str, parameters: &[ParameterSpec], defaults: &[ParameterValue], presets: &[Preset]) -> Result<()> {
+    let document = document()?;
+    let Some(container) = document.get_element_by_id("parameter-list") else {
+        return Ok(());
+    };
+
+    let group = create_element(&document, "div")?;
+    group.set_class_name("param-group");
+    group.set_inner_html(
+        r#"<label for="preset-select">Preset:</label>
+        <select id="preset-select"></select>
+        <button type="button" id="preset-load">Load</button>
+        <button type="button" id="preset-delete">Delete</button>
+        <input type="text" id="preset-name" placeholder="Preset name">
+        <button type="button" id="preset-save">Save</button>"#,
+    );
+    container
+        .insert_before(&group, container.first_child().as_ref())
+        .map_err(|err| anyhow!("Failed to insert preset group: {:#?}", err))?;
+
+    let select = document
+        .get_element_by_id("preset-select")
+        .ok_or_else(|| anyhow!("No element found with id 'preset-select'"))?
+        .dyn_into::<HtmlSelectElement>()
+        .map_err(|err| anyhow!("Failed to convert element to HtmlSelectElement: {:#?}", err))?;
+    let builtins: Vec<(String, String)> = presets.iter().map(|preset| (preset.name.to_string(), preset.query(parameters, defaults))).collect();
+    fill_preset_select(&document, &select, game, &builtins)?;
+
+    let parameters = parameters.to_vec();
+    add_click_listener("preset-load", {
+        let select = select.clone();
+        let parameters = parameters.clone();
+        move || {
+            apply_parameter_query(&parameters, &select.value())?;
+            write_parameters_to_url(&parameters)
+        }
+    })?;
+    add_click_listener("preset-save", {
+        let document = document.clone();
+        let select = select.clone();
+        let builtins = builtins.clone();
+        move || {
+            let name = input_element("preset-name")?.value();
+            let name = name.trim();
+            if name.is_empty() {
+                return Err(anyhow!("Preset name is empty"));
+            }
+            local_storage()?
+                .set_item(&format!("preset:{}:{}", game, name), &parameter_query(&parameters)?)
+                .map_err(|err| anyhow!("Failed to save preset: {:#?}", err))?;
+            fill_preset_select(&document, &select, game, &builtins)
+        }
+    })?;
+    add_click_listener("preset-delete", move || {
+        let Ok(index) = u32::try_from(select.selected_index()) else {
+            return Ok(());
+        };
+        let Some(option) = select.item(index) else {
+            return Ok(());
+        };
+        let Some(key) = option.get_attribute("data-key") else {
+            return Err(anyhow!("Built-in presets cannot be deleted"));
+        };
+        local_storage()?
+            .remove_item(&key)
+            .map_err(|err| anyhow!("Failed to delete preset: {:#?}", err))?;
+        fill_preset_select(&document, &select, game, &builtins)
+    })?;
+
+    Ok(())
+}
+
+// 組み込みのプリセットのあとにlocalStorageのプリセットを並べる (optionの値はquery)
+fn fill_preset_select(document: &Document, select: &HtmlSelectElement, game: &str, builtins: &[(String, String)]) -> Result<()> {
+    select.set_inner_html("");
+    // localStorageが使えないときは組み込みのプリセットだけにする
+    let saved = saved_presets(game).unwrap_or_else(|err| {
+        log!("{:#}", err);
+        Vec::new()
+    });
+
+    for (label, presets) in [
+        ("Built-in", builtins.iter().map(|(name, query)| (name.clone(), query.clone(), None)).collect::<Vec<_>>()),
+        ("Saved", saved.into_iter().map(|(name, query, key)| (name, query, Some(key))).collect()),
+    ] {
+        if presets.is_empty() {
+            continue;
+        }
+        let group = create_element(document, "optgroup")?;
+        set_attribute(&group, "label", label)?;
+        for (name, query, key) in presets {
+            let option = create_element(document, "option")?;
+            set_attribute(&option, "value", &query)?;
+            if let Some(key) = key {
+                set_attribute(&option, "data-key", &key)?;
+            }
+            option.set_text_content(Some(&name));
+            group.append_child(&option).map_err(|err| anyhow!("Failed to append option: {:#?}", err))?;
+        }
+        select.append_child(&group).map_err(|err| anyhow!("Failed to append optgroup: {:#?}", err))?;
+    }
+    Ok(())
+}
+
+// localStorageに保存したプリセットの (名前, クエリ, キー) を名前順に
+fn saved_presets(game: &str) -> Result<Vec<(String, String, String)>> {
+    let storage = local_storage()?;
+    let prefix = format!("preset:{}:", game);
+    let mut saved = Vec::new();
+    for i in 0..storage.length().map_err(|err| anyhow!("Failed to read localStorage: {:#?}", err))? {
+        let Some(key) = storage.key(i).ok().flatten() else {
+            continue;
+        };
+        if let (Some(name), Some(query)) = (key.strip_prefix(&prefix), storage.get_item(&key).ok().flatten()) {
+            saved.push((name.to_string(), query, key.clone()));
+        }
+    }
+    saved.sort();
+    Ok(saved)
+}
+
+// 失敗したらログに出すだけ
+fn add_click_listener(id: &str, on_click: impl Fn() -> Result<()> + 'static) -> Result<()> {
+    let element = document()?
+        .get_element_by_id(id)
+        .ok_or_else(|| anyhow!("No element found with id '{}'", id))?;
+    let closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        if let Err(err) = on_click() {
+            log!("{:#}", err);
+        }
+    }) as Box<dyn FnMut(_)>);
+    element
+        .add_event_listener_with_callback("click", closure.as_ref().unchecked_ref())
+        .map_err(|err| anyhow!("Failed to add event listener: {:#?}", err))?;
+    closure.forget();
+    Ok(())
+}
+
+// パネルの値を変え終わったら (スライダーを離したときなど) URLを書き換える
+// inputのたびに書き換えるとブラウザに回数を制限されるのでchangeで
+pub fn sync_parameters_to_url(parameters: &[ParameterSpec]) -> Result<()> {
+    let Some(container) = document()?.get_element_by_id("parameter-list") else {
+        return Ok(());
+    };
+    let parameters = parameters.to_vec();
+    let closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+        let is_parameter = event
+            .target()
+            .and_then(|target| target.dyn_into::<Element>().ok())
+            .is_some_and(|element| element.id().starts_with("param-"));
+        if is_parameter {
+            if let Err(err) = write_parameters_to_url(&parameters) {
+                log!("{:#}", err);
+            }
+        }
+    }) as Box<dyn FnMut(_)>);
+    container
+        .add_event_listener_with_callback("change", closure.as_ref().unchecked_ref())
+        .map_err(|err| anyhow!("Failed to add event listener: {:#?}", err))?;
+    closure.forget();
+    Ok(())
+}
+
+fn local_storage() -> Result<Storage> {
+    window()?
+        .local_storage()
+        .map_err(|err| anyhow!("Failed to get localStorage: {:#?}", err))?
+        .ok_or_else(|| anyhow!("localStorage is not available"))
+}
+
+fn input_element(id: &str) -> Result<HtmlInputElement> {
+    document()?
+        .get_element_by_id(id)
+        .ok_or_else(|| anyhow!("No element found with id '{}'", id))?
+        .dyn_into::<HtmlInputElement>()
+        .map_err(|err| anyhow!("Failed to convert element to HtmlInputElement: {:#?}", err))
+}
+
+fn create_element(document: &Document, tag: &str) -> Result<Element> {
+    document
+        .create_element(tag)
+        .map_err(|err| anyhow!("Failed to create {}: {:#?}", tag, err))
+}
+
+fn set_attribute(element: &Element, name: &str, value: &str) -> Result<()> {
+    element
+        .set_attribute(name, value)
+        .map_err(|err| anyhow!("Failed to set {}: {:#?}", name, err))
+}
+
+// スライダーと数値入力を同期させ、値が変わるたびにon_inputを呼ぶ
+pub fn set_parameter_ui(name: &str, on_input: impl Fn(f64) + Clone + 'static) -> Result<()> {
     log!("init parameter ui slider: {}", name);
     let document = document()?;
 
@@ -165,9 +483,13 @@ pub fn set_parameter_ui(name: &str) -> Result<()> {
     {
         let slider_clone = slider.clone();
         let number_clone = number_input.clone();
+        let on_input = on_input.clone();
         let slider_closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
             let value = slider_clone.value();
             number_clone.set_value(&value);
+            if let Ok(value) = value.parse() {
+                on_input(value);
+            }
         }) as Box<dyn FnMut(_)>);
 
         slider.add_event_listener_with_callback("input", slider_closure.as_ref().unchecked_ref())
@@ -183,6 +505,10 @@ pub fn set_parameter_ui(name: &str) -> Result<()> {
             let value = number_clone.value();
             number_clone.set_value(&value);
             slider_clone.set_value(&value);
+            // 入力途中で空のときなどは送らない
+            if let Ok(value) = value.parse() {
+                on_input(value);
+            }
         }) as Box<dyn FnMut(_)>);
 
         number_input.add_event_listener_with_callback("input", number_closure.as_ref().unchecked_ref())
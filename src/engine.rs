@@ -2,14 +2,16 @@
 use wasm_bindgen::prelude::*;
 use anyhow::{anyhow, Ok, Result};
 use std::{
-    cell::{RefCell}, rc::Rc,
+    cell::{Cell, RefCell}, rc::Rc,
     sync::atomic::{AtomicBool, Ordering},
 };
+use futures::channel::mpsc::{unbounded, UnboundedReceiver};
 
 use wasm_bindgen::{JsValue,};
-use web_sys::{CanvasRenderingContext2d,HtmlInputElement};
+use web_sys::CanvasRenderingContext2d;
 
 use crate::browser::{self, LoopClosure};
+use crate::parameter::{ParameterChange, ParameterSpec, ParameterValue, Preset};
 
 // グローバルな停止フラグ
 static LOOP_RUNNING: AtomicBool = AtomicBool::new(true);
@@ -23,12 +25,40 @@ pub fn stop_loop() {
 #[async_trait::async_trait(?Send)]
 pub trait Game {
     async fn initialize(&self) -> Result<Box<dyn Game>>;
-    fn update(&mut self);
+    fn update(&mut self, dt: f64); // dtは前回のupdateからの経過時間 (秒)
     fn draw(&self, renderer: &Renderer2d);
-    fn update_parameter_from_html(&mut self) {}
+    fn name(&self) -> &'static str { "game" } // プリセットの保存先を分けるのに使う
+    fn parameters(&self) -> Vec<ParameterSpec> { Vec::new() } // パネルに出すパラメータ
+    fn parameter_values(&self) -> Vec<ParameterValue> { Vec::new() } // parametersと同じ並びの今の値
+    fn parameter_defaults(&self) -> Vec<ParameterValue> { self.parameter_values() } // プリセットに無いパラメータに使う値
+    fn handle_parameter(&mut self, _change: &ParameterChange) {}
+    fn presets(&self) -> Vec<Preset> { Vec::new() } // 組み込みのプリセット
+    fn handle_pointer(&mut self, _event: &PointerEvent) {}
+    fn handle_action(&mut self, _action: &str) {} // data-action属性のあるボタンが押された
+    fn on_resize(&mut self, _width: u32, _height: u32) {} // canvasの大きさが変わった
+}
+
+// canvas上のマウス・タッチ操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerKind {
+    Down,
+    Move,
+    Up,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PointerEvent {
+    pub kind: PointerKind,
+    pub position: Point, // canvas内の座標
+    pub button: i16,     // 0: 左, 1: 中, 2: 右 (タッチは0)
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
 }
 
 const FRAME_SIZE: f32 = 1.0 / 60.0 * 1000.0; // 60fps
+const MAX_CATCH_UP: f32 = 250.0; // 1フレームで追いつく時間の上限 (ms)
+const RESIZE_DEBOUNCE: i32 = 200; // ウィンドウの大きさが変わり終わってからcanvasを合わせるまでの待ち (ms)
 pub struct GameLoop {
     last_time: f64,
     accumulated_delta_time: f32,
@@ -50,8 +80,27 @@ impl GameLoop {
             context: browser::context2d()?,
         };
 
+        let (mut pointer_receiver, pointer_listeners) = prepare_pointer_input()?;
+        let (mut action_receiver, action_listener) = prepare_action_input()?;
+        let mut input_listeners: Vec<EventListener> = pointer_listeners.into_iter().chain([action_listener]).collect();
+        let (mut resize_receiver, mut resize_listener) = prepare_resize_input()?;
+
+        // パラメータパネルはゲームの宣言から作る
         let param_check = browser::check_parameter_ui()?;
         log!("parameter ui is exist: {}", param_check);
+        let parameters = game.parameters();
+        let mut parameter_values = game.parameter_values();
+        if param_check {
+            browser::build_parameter_panel(&parameters, &parameter_values)?;
+        }
+        let mut parameter_receiver = prepare_parameter_input(&parameters, param_check)?;
+        // URLに値があればそれにして、URLにはいつも全部の値が入っているようにする
+        if param_check && !parameters.is_empty() {
+            browser::build_preset_ui(game.name(), &parameters, &game.parameter_defaults(), &game.presets())?;
+            browser::apply_parameter_query(&parameters, &browser::url_query()?)?;
+            browser::write_parameters_to_url(&parameters)?;
+            browser::sync_parameters_to_url(&parameters)?;
+        }
         let mut last_fps_update = 0.0;
 
         let f: SharedLoopClosure = Rc::new(RefCell::new(None));
@@ -59,23 +108,53 @@ impl GameLoop {
         *g.borrow_mut() = Some(browser::create_ref_closure(move |perf:f64|{
             if !LOOP_RUNNING.load(Ordering::Relaxed) {
                 log!("Game Loop: Stopped");
+                if let Some(listener) = resize_listener.take() {
+                    if let Err(err) = listener.remove() {
+                        log!("{:#?}", err);
+                    }
+                }
+                for listener in input_listeners.drain(..) {
+                    if let Err(err) = listener.remove() {
+                        log!("{:#?}", err);
+                    }
+                }
                 renderer.clear();
                 return;
             }
             //process_input(&mut keystate, &mut keyevent_receiver);
+            while let std::result::Result::Ok(event) = pointer_receiver.try_recv() {
+                game.handle_pointer(&event);
+            }
+            while let std::result::Result::Ok(action) = action_receiver.try_recv() {
+                game.handle_action(&action);
+            }
+            // 何回か来ていたら最後の大きさだけ渡す
+            let mut size = None;
+            while let std::result::Result::Ok(next) = resize_receiver.try_recv() {
+                size = Some(next);
+            }
+            if let Some((width, height)) = size {
+                game.on_resize(width, height);
+            }
+            // 丸めると同じ値になる入力 (整数のパラメータの途中の値など) は渡さない
+            while let std::result::Result::Ok(change) = parameter_receiver.try_recv() {
+                if parameter_values.get(change.index).is_some_and(|value| *value != change.value) {
+                    parameter_values[change.index] = change.value;
+                    game.handle_parameter(&change);
+                }
+            }
             let frame_time = perf - game_loop.last_time;
             game_loop.accumulated_delta_time += frame_time as f32;
 
-            let mut count = 0;
+            // 固定の時間刻みで、遅れた分は何回かupdateして追いつく
+            // タブが裏にあったときなど遅れすぎた分は追いつくのをあきらめる
+            if game_loop.accumulated_delta_time > MAX_CATCH_UP {
+                log!("Game Loop: Too many updates, skipping some frames.");
+                game_loop.accumulated_delta_time = FRAME_SIZE;
+            }
             while game_loop.accumulated_delta_time >= FRAME_SIZE{
-                game.update();
+                game.update(FRAME_SIZE as f64 / 1000.0);
                 game_loop.accumulated_delta_time -= FRAME_SIZE;
-                count += 1;
-                if count > 3 {
-                    log!("Game Loop: Too many updates, skipping some frames.");
-                    game_loop.accumulated_delta_time = 0.0;
-                    break;
-                }
             }
             game_loop.last_time = perf;
             game.draw(&renderer);
@@ -89,7 +168,6 @@ impl GameLoop {
                 if last_fps_update + 100.0 < perf {
                     draw_frame_rate(frame_time);
                     last_fps_update = perf;
-                    game.update_parameter_from_html();
                 }
             }
             
@@ -111,7 +189,7 @@ impl GameLoop {
 }
 
 // math utilsみたいなものになるんかなぁ
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Point {
     pub x: f64,
     pub y: f64,
@@ -127,6 +205,172 @@ impl Point {
     }
 }
 
+impl std::ops::Add for Point {
+    type Output = Point;
+    fn add(self, other: Point) -> Point {
+        Point::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl std::ops::Sub for Point {
+    type Output = Point;
+    fn sub(self, other: Point) -> Point {
+        Point::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl std::ops::Mul<f64> for Point {
+    type Output = Point;
+    fn mul(self, scale: f64) -> Point {
+        Point::new(self.x * scale, self.y * scale)
+    }
+}
+
+// 2次元と3次元のベクトルに共通の演算 (ボイドの操舵や近傍探索を次元によらず書くため)
+pub trait Vector: Copy + std::ops::Add<Output = Self> + std::ops::Sub<Output = Self> + std::ops::Mul<f64, Output = Self> {
+    const DIM: usize;
+    const ZERO: Self;
+
+    fn axis(self, i: usize) -> f64; // i番目の成分 (DIM以上は0)
+    fn zip_map(self, other: Self, f: impl Fn(f64, f64) -> f64) -> Self; // 成分ごとにfを適用する
+
+    fn map(self, f: impl Fn(f64) -> f64) -> Self {
+        self.zip_map(self, |a, _| f(a))
+    }
+
+    fn dot(self, other: Self) -> f64 {
+        (0..Self::DIM).map(|i| self.axis(i) * other.axis(i)).sum()
+    }
+
+    fn length(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    // 向きはそのままで長さをlengthにする (ゼロベクトルはゼロのまま)
+    fn scaled(self, length: f64) -> Self {
+        let norm = self.length();
+        if norm == 0.0 {
+            Self::ZERO
+        } else {
+            self * (length / norm)
+        }
+    }
+
+    // 長さがmaxを超えていたらmaxに縮める
+    fn limited(self, max: f64) -> Self {
+        if self.dot(self) > max * max {
+            self.scaled(max)
+        } else {
+            self
+        }
+    }
+}
+
+impl Vector for Point {
+    const DIM: usize = 2;
+    const ZERO: Self = Point { x: 0.0, y: 0.0 };
+
+    fn axis(self, i: usize) -> f64 {
+        match i {
+            0 => self.x,
+            1 => self.y,
+            _ => 0.0,
+        }
+    }
+
+    fn zip_map(self, other: Self, f: impl Fn(f64, f64) -> f64) -> Self {
+        Point::new(f(self.x, other.x), f(self.y, other.y))
+    }
+}
+
+impl Vector for Vec3 {
+    const DIM: usize = 3;
+    const ZERO: Self = Vec3::ZERO;
+
+    fn axis(self, i: usize) -> f64 {
+        match i {
+            0 => self.x,
+            1 => self.y,
+            2 => self.z,
+            _ => 0.0,
+        }
+    }
+
+    fn zip_map(self, other: Self, f: impl Fn(f64, f64) -> f64) -> Self {
+        Vec3::new(f(self.x, other.x), f(self.y, other.y), f(self.z, other.z))
+    }
+}
+
+// 3次元のベクトル
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vec3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Vec3 {
+    pub const ZERO: Vec3 = Vec3 { x: 0.0, y: 0.0, z: 0.0 };
+
+    pub fn new(x: f64, y: f64, z: f64) -> Self {
+        Self {x, y, z}
+    }
+
+    pub fn dot(self, other: Vec3) -> f64 {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn cross(self, other: Vec3) -> Vec3 {
+        Vec3::new(
+            self.y * other.z - self.z * other.y,
+            self.z * other.x - self.x * other.z,
+            self.x * other.y - self.y * other.x,
+        )
+    }
+
+    pub fn length_sq(self) -> f64 {
+        self.dot(self)
+    }
+
+    pub fn length(self) -> f64 {
+        self.length_sq().sqrt()
+    }
+}
+
+impl std::ops::Add for Vec3 {
+    type Output = Vec3;
+    fn add(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x + other.x, self.y + other.y, self.z + other.z)
+    }
+}
+
+impl std::ops::AddAssign for Vec3 {
+    fn add_assign(&mut self, other: Vec3) {
+        *self = *self + other;
+    }
+}
+
+impl std::ops::Sub for Vec3 {
+    type Output = Vec3;
+    fn sub(self, other: Vec3) -> Vec3 {
+        Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z)
+    }
+}
+
+impl std::ops::Mul<f64> for Vec3 {
+    type Output = Vec3;
+    fn mul(self, scale: f64) -> Vec3 {
+        Vec3::new(self.x * scale, self.y * scale, self.z * scale)
+    }
+}
+
+impl std::ops::Neg for Vec3 {
+    type Output = Vec3;
+    fn neg(self) -> Vec3 {
+        Vec3::new(-self.x, -self.y, -self.z)
+    }
+}
+
 // 2D描画用のレンダラー
 pub struct Renderer2d{
     pub context: CanvasRenderingContext2d,
@@ -160,14 +404,56 @@ impl Renderer2d {
         self.context.close_path();
     }
 
-    pub fn triangle(&self, x1: f64, y1: f64, x2: f64, y2: f64, x3: f64, y3: f64) {
+    // 扇形 (角度はラジアン、x軸から時計回り)
+    pub fn sector(&self, center: Point, radius: f64, start_angle: f64, end_angle: f64, color: &str) {
+        let color_str = get_color(color);
+        self.context.begin_path();
+        self.context.move_to(center.x, center.y);
+        self.context.arc(center.x, center.y, radius, start_angle, end_angle).unwrap();
+        self.context.close_path();
+        self.context.set_fill_style(&JsValue::from_str(color_str.as_str()));
+        self.context.fill();
+    }
+
+    pub fn rect(&self, top_left: Point, width: f64, height: f64, color: &str) {
+        let color_str = get_color(color);
+        self.context.set_fill_style(&JsValue::from_str(color_str.as_str()));
+        self.context.fill_rect(top_left.x, top_left.y, width, height);
+    }
+
+    pub fn polygon(&self, points: &[Point], color: &str) {
+        let Some((first, rest)) = points.split_first() else {
+            return;
+        };
+        let color_str = get_color(color);
+        self.context.begin_path();
+        self.context.move_to(first.x, first.y);
+        for point in rest {
+            self.context.line_to(point.x, point.y);
+        }
+        self.context.close_path();
+        self.context.set_fill_style(&JsValue::from_str(color_str.as_str()));
+        self.context.fill();
+    }
+
+    // 左上がpositionになるように文字を書く
+    pub fn text(&self, position: Point, text: &str, size: f64, color: &str) {
+        let color_str = get_color(color);
+        self.context.set_font(&format!("{}px monospace", size));
+        self.context.set_text_baseline("top");
+        self.context.set_fill_style(&JsValue::from_str(color_str.as_str()));
+        let _ = self.context.fill_text(text, position.x, position.y);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn triangle(&self, x1: f64, y1: f64, x2: f64, y2: f64, x3: f64, y3: f64, color: &str) {
         self.context.begin_path();
         self.context.move_to(x1, y1);
         self.context.line_to(x2, y2);
         self.context.line_to(x3, y3);
         self.context.close_path();
         // 中を塗りつぶす
-        self.context.set_fill_style(&JsValue::from_str("rgba(0, 255, 0, 0.9)"));
+        self.context.set_fill_style(&JsValue::from_str(&get_color(color)));
         self.context.fill();
         self.context.stroke();
     }
@@ -179,6 +465,7 @@ fn get_color(color: &str) -> String {
         "red" => "rgba(255, 0, 0, 0.9)".to_string(),
         "green" => "rgba(0, 255, 0, 0.9)".to_string(),
         "blue" => "rgba(0, 0, 255, 0.9)".to_string(),
+        _ if color.starts_with("rgb") || color.starts_with('#') => color.to_string(), // css形式はそのまま使う
         _ => "rgba(10, 10, 10, 0.9)".to_string(), // default to black
     }
 }
@@ -190,24 +477,186 @@ fn draw_frame_rate(frame_time: f64) {
     });
 }
 
-pub fn get_parameter_ui_value(name: &str) -> Result<f64> {
+// 文字列をファイルとしてダウンロードさせる
+pub fn download(filename: &str, mime: &str, content: &str) -> Result<()> {
+    let document = browser::document()?;
+    let parts = web_sys::js_sys::Array::of1(&JsValue::from_str(content));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime);
+    let blob = web_sys::Blob::new_with_str_sequence_and_options(&parts, &options)
+        .map_err(|err| anyhow!("Failed to create blob: {:#?}", err))?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob)
+        .map_err(|err| anyhow!("Failed to create object url: {:#?}", err))?;
+
+    let anchor = document
+        .create_element("a")
+        .map_err(|err| anyhow!("Failed to create anchor: {:#?}", err))?
+        .dyn_into::<web_sys::HtmlAnchorElement>()
+        .map_err(|err| anyhow!("Failed to convert element to HtmlAnchorElement: {:#?}", err))?;
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+
+    web_sys::Url::revoke_object_url(&url).map_err(|err| anyhow!("Failed to revoke object url: {:#?}", err))?;
+    Ok(())
+}
+
+// canvasのpointerイベント (マウスとタッチ) をチャンネルに流す
+// ゲームループが毎フレーム取り出してGame::handle_pointerに渡す
+type PointerInput = (UnboundedReceiver<PointerEvent>, Vec<EventListener>);
+fn prepare_pointer_input() -> Result<PointerInput> {
+    let (sender, receiver) = unbounded();
+    let canvas = browser::canvas()?;
+    let mut listeners = Vec::new();
+    for (name, kind) in [
+        ("pointerdown", PointerKind::Down),
+        ("pointermove", PointerKind::Move),
+        ("pointerup", PointerKind::Up),
+        ("pointercancel", PointerKind::Up),
+    ] {
+        let sender = sender.clone();
+        let target = canvas.clone();
+        let closure = browser::closure_wrap(Box::new(move |event: web_sys::PointerEvent| {
+            if kind == PointerKind::Down {
+                event.prevent_default(); // タッチでのスクロールなどを止める
+                // ドラッグ中にcanvasの外に出てもupを受け取れるようにする
+                let _ = target.set_pointer_capture(event.pointer_id());
+            }
+            let _ = sender.unbounded_send(PointerEvent {
+                kind,
+                position: Point::new(event.offset_x() as f64, event.offset_y() as f64),
+                button: event.button().max(0),
+                shift: event.shift_key(),
+                ctrl: event.ctrl_key() || event.meta_key(),
+                alt: event.alt_key(),
+            });
+        }) as Box<dyn FnMut(web_sys::PointerEvent)>);
+        listeners.push(EventListener::add(canvas.clone().into(), name, closure)?);
+    }
+    // 右クリックメニューを出さない
+    let closure = browser::closure_wrap(Box::new(|event: web_sys::Event| event.prevent_default()) as Box<dyn FnMut(web_sys::Event)>);
+    listeners.push(EventListener::add(canvas.into(), "contextmenu", closure)?);
+
+    Ok((receiver, listeners))
+}
+
+// data-action属性のある要素のクリックをチャンネルに流す (値はdata-actionの文字列)
+// ゲームループが毎フレーム取り出してGame::handle_actionに渡す
+fn prepare_action_input() -> Result<(UnboundedReceiver<String>, EventListener)> {
+    let (sender, receiver) = unbounded();
     let document = browser::document()?;
-    // let number_input = document
-    //     .get_element_by_id(format!("param-input-{}", name).as_str())
-    //     .ok_or_else(|| anyhow!("No element found with id 'param-{}'", name))?
-    //     .dyn_into::<HtmlInputElement>()
-    //     .map_err(|err| anyhow!("Failed to convert element to HtmlInputElement: {:#?}", err))?;
-    let slider = document
-        .get_element_by_id(format!("param-slider-{}", name).as_str())
-        .ok_or_else(|| anyhow!("No element found with id 'param-{}'", name))?
-        .dyn_into::<HtmlInputElement>()
-        .map_err(|err| anyhow!("Failed to convert element to HtmlInputElement: {:#?}", err))?;
-    let value = slider.value();
-    //let value = number_input.value();
-    let value: f64 = value.parse().map_err(|err| anyhow!("Failed to parse value: {:#?}", err))?;
-    Ok(value)
-}
-
-// parameter input
-// html側でパラメータのスライドを変化させたり、値を入力したときにeventを受け取る
-// fn prepare_input() -> Result<UnboundedReciever<
\ No newline at end of file
+    let closure = browser::closure_wrap(Box::new(move |event: web_sys::Event| {
+        let action = event
+            .target()
+            .and_then(|target| target.dyn_into::<web_sys::Element>().ok())
+            .and_then(|element| element.closest("[data-action]").ok().flatten())
+            .and_then(|element| element.get_attribute("data-action"));
+        if let Some(action) = action {
+            let _ = sender.unbounded_send(action);
+        }
+    }) as Box<dyn FnMut(web_sys::Event)>);
+    let listener = EventListener::add(document.into(), "click", closure)?;
+
+    Ok((receiver, listener))
+}
+
+// ループが止まったら外すイベントリスナー (pointerとdata-actionのクリック)
+// 外さないと別のページに移ったあとも前のゲームのチャンネルに送り続ける
+struct EventListener {
+    target: web_sys::EventTarget,
+    name: &'static str,
+    closure: Box<dyn AsRef<JsValue>>, // Closure<dyn FnMut(..)> (引数の型はイベントごとに違う)
+}
+
+impl EventListener {
+    fn add(target: web_sys::EventTarget, name: &'static str, closure: impl AsRef<JsValue> + 'static) -> Result<Self> {
+        target
+            .add_event_listener_with_callback(name, closure.as_ref().unchecked_ref())
+            .map_err(|err| anyhow!("Failed to add {} listener: {:#?}", name, err))?;
+        Ok(Self { target, name, closure: Box::new(closure) })
+    }
+
+    fn remove(self) -> Result<()> {
+        self.target
+            .remove_event_listener_with_callback(self.name, (*self.closure).as_ref().unchecked_ref())
+            .map_err(|err| anyhow!("Failed to remove {} listener: {:#?}", self.name, err))
+    }
+}
+
+// ウィンドウの大きさが変わったら、RESIZE_DEBOUNCEだけ何も起きないのを待ってから
+// canvasを画面いっぱいにし直して、新しい (幅, 高さ) をチャンネルに流す
+// ゲームループが毎フレーム取り出してGame::on_resizeに渡す
+// canvasの大きさが決まっているページでは何も流れない (リスナーも付けない)
+type ResizeInput = (UnboundedReceiver<(u32, u32)>, Option<ResizeListener>);
+fn prepare_resize_input() -> Result<ResizeInput> {
+    let (sender, receiver) = unbounded();
+    if !browser::is_canvas_fullscreen() {
+        return Ok((receiver, None));
+    }
+    let timer: Rc<Cell<Option<i32>>> = Rc::new(Cell::new(None));
+    let on_timeout = {
+        let timer = timer.clone();
+        browser::closure_wrap(Box::new(move || {
+            timer.set(None);
+            match browser::set_canvas_fullscreen() {
+                std::result::Result::Ok(size) => {
+                    let _ = sender.unbounded_send(size);
+                }
+                Err(err) => log!("Failed to resize canvas: {:#?}", err),
+            }
+        }) as Box<dyn FnMut()>)
+    };
+    let on_resize = {
+        let timer = timer.clone();
+        browser::closure_wrap(Box::new(move || {
+            if let Some(id) = timer.take() {
+                let _ = browser::clear_timeout(id);
+            }
+            match browser::set_timeout(&on_timeout, RESIZE_DEBOUNCE) {
+                std::result::Result::Ok(id) => timer.set(Some(id)),
+                Err(err) => log!("Failed to wait for resize: {:#?}", err),
+            }
+        }) as Box<dyn FnMut()>)
+    };
+    browser::window()?
+        .add_event_listener_with_callback("resize", on_resize.as_ref().unchecked_ref())
+        .map_err(|err| anyhow!("Failed to add resize listener: {:#?}", err))?;
+
+    Ok((receiver, Some(ResizeListener { on_resize, timer })))
+}
+
+// ループが止まったら外すresizeリスナー
+// 外さないと別のページに移ったあともcanvasを画面いっぱいにし直してしまう
+struct ResizeListener {
+    on_resize: Closure<dyn FnMut()>,
+    timer: Rc<Cell<Option<i32>>>, // 待っているタイマーのID
+}
+
+impl ResizeListener {
+    fn remove(self) -> Result<()> {
+        if let Some(id) = self.timer.take() {
+            browser::clear_timeout(id)?;
+        }
+        browser::window()?
+            .remove_event_listener_with_callback("resize", self.on_resize.as_ref().unchecked_ref())
+            .map_err(|err| anyhow!("Failed to remove resize listener: {:#?}", err))
+    }
+}
+
+// パラメータパネルのinputイベントを型つきの値にしてチャンネルに流す
+// ゲームループが毎フレーム取り出してGame::handle_parameterに渡す
+// パネルが無いページでは何も流れない
+fn prepare_parameter_input(parameters: &[ParameterSpec], param_check: bool) -> Result<UnboundedReceiver<ParameterChange>> {
+    let (sender, receiver) = unbounded();
+    if !param_check {
+        return Ok(receiver);
+    }
+    for (index, spec) in parameters.iter().copied().enumerate() {
+        let sender = sender.clone();
+        browser::set_parameter_ui(spec.name, move |raw| {
+            let _ = sender.unbounded_send(ParameterChange { index, value: spec.value_from(raw) });
+        })?;
+    }
+
+    Ok(receiver)
+}
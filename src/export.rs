@@ -0,0 +1,212 @@
+// Universeの一部を画像に書き出す (PNG / アニメーションGIF)
+// ブラウザに依存しないのでネイティブのテストからも使える
+use anyhow::{anyhow, bail, Result};
+use wasm_bindgen::prelude::*;
+
+use crate::hashlife::{Pattern, Position, Rule, Universe};
+
+const MAX_PIXELS: u64 = 1 << 26; // 1枚の画像のピクセル数の上限 (1ピクセル1バイトで64MB)
+
+// 状態ごとの色 (添字が状態 0番目が背景)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<[u8; 3]>) -> Result<Self> {
+        if colors.len() < 2 || colors.len() > 256 {
+            bail!("a palette needs between 2 and 256 colors, got {}", colors.len());
+        }
+        Ok(Self { colors })
+    }
+
+    // 画面の描画と同じ配色 (背景は黒、生は緑、死にかけは青から暗くなっていく)
+    pub fn for_rule(rule: Rule) -> Self {
+        let states = rule.states();
+        let mut colors = vec![[0, 0, 0], [0, 255, 0]];
+        for n in 2..states {
+            let t = (n - 1) as f64 / (states - 1) as f64;
+            let fade = 0.9 - 0.7 * t;
+            colors.push([0, (160.0 * (1.0 - t) * fade) as u8, (255.0 * fade) as u8]);
+        }
+        Self { colors }
+    }
+
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+
+    // パレットに無い状態は最後の色で塗る
+    fn index(&self, state: u8) -> u8 {
+        state.min((self.colors.len() - 1) as u8)
+    }
+
+    fn flat(&self) -> Vec<u8> {
+        self.colors.iter().flatten().copied().collect()
+    }
+}
+
+// 書き出す範囲 (セル座標でmin以上max未満) と1セルのピクセル数
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub min: Position,
+    pub max: Position,
+    pub cell_size: u32,
+}
+
+impl Region {
+    pub fn new(min: impl Into<Position>, max: impl Into<Position>, cell_size: u32) -> Result<Self> {
+        let (min, max) = (min.into(), max.into());
+        if max.x <= min.x || max.y <= min.y {
+            bail!("empty region {:?}..{:?}", min, max);
+        }
+        if cell_size == 0 {
+            bail!("cell size must be at least 1 pixel");
+        }
+        let region = Self { min, max, cell_size };
+        let (width, height) = region.pixel_size();
+        if width > u16::MAX as u64 || height > u16::MAX as u64 {
+            bail!("image would be {}x{} pixels, the limit is {}", width, height, u16::MAX);
+        }
+        if width * height > MAX_PIXELS {
+            bail!("image would have {} pixels, the limit is {}", width * height, MAX_PIXELS);
+        }
+        Ok(region)
+    }
+
+    pub fn pixel_size(&self) -> (u64, u64) {
+        (
+            (self.max.x - self.min.x) as u64 * self.cell_size as u64,
+            (self.max.y - self.min.y) as u64 * self.cell_size as u64,
+        )
+    }
+}
+
+// 範囲内のセルをパレットの添字の画像にする
+fn rasterize(universe: &Universe, region: &Region, palette: &Palette) -> Vec<u8> {
+    let (width, height) = region.pixel_size();
+    let size = region.cell_size as usize;
+    let mut pixels = vec![0u8; (width * height) as usize];
+    for (pos, cell) in universe.cells_in(region.min, region.max) {
+        let px = (pos.x - region.min.x) as usize * size;
+        let py = (pos.y - region.min.y) as usize * size;
+        let index = palette.index(cell.state());
+        for row in py..py + size {
+            let start = row * width as usize + px;
+            pixels[start..start + size].fill(index);
+        }
+    }
+    pixels
+}
+
+pub fn encode_png(universe: &Universe, region: &Region, palette: &Palette) -> Result<Vec<u8>> {
+    let (width, height) = region.pixel_size();
+    let pixels = rasterize(universe, region, palette);
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(palette.flat());
+        let mut writer = encoder
+            .write_header()
+            .map_err(|err| anyhow!("Failed to write png header: {:#?}", err))?;
+        writer
+            .write_image_data(&pixels)
+            .map_err(|err| anyhow!("Failed to write png data: {:#?}", err))?;
+    }
+    Ok(bytes)
+}
+
+// 今の世代から始めてgenerations世代進めたアニメーション (フレーム数はgenerations + 1)
+// universeはその分進んだ状態になる
+pub fn encode_gif(
+    universe: &mut Universe,
+    region: &Region,
+    palette: &Palette,
+    generations: usize,
+    frame_delay_ms: u32,
+) -> Result<Vec<u8>> {
+    let (width, height) = region.pixel_size();
+    let flat_palette = palette.flat();
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut bytes, width as u16, height as u16, &flat_palette)
+            .map_err(|err| anyhow!("Failed to create gif encoder: {:#?}", err))?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(|err| anyhow!("Failed to set gif repeat: {:#?}", err))?;
+
+        for generation in 0..=generations {
+            if generation > 0 {
+                universe.evolve();
+            }
+            let pixels = rasterize(universe, region, palette);
+            let mut frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, pixels, None);
+            frame.delay = (frame_delay_ms / 10).min(u16::MAX as u32) as u16; // 単位は1/100秒
+            encoder
+                .write_frame(&frame)
+                .map_err(|err| anyhow!("Failed to write gif frame: {:#?}", err))?;
+        }
+    }
+    Ok(bytes)
+}
+
+// JSから渡された左上と大きさの領域 (i32のまま足すとあふれるのでi64で足す)
+fn js_region(x: i32, y: i32, width: i32, height: i32, cell_size: u32) -> Result<Region, JsValue> {
+    let (x, y) = (x as i64, y as i64);
+    Region::new((x, y), (x + width as i64, y + height as i64), cell_size)
+        .map_err(|err| JsValue::from_str(&format!("{:#?}", err)))
+}
+
+// ブラウザからダウンロード用の画像を作る
+// let export = new LifeExport("23/3/2", ".O.\n..O\nOOO");
+// const png = export.png(-8, -8, 16, 16, 8); // Uint8Array
+#[wasm_bindgen]
+pub struct LifeExport {
+    universe: Universe,
+    palette: Palette,
+}
+
+#[wasm_bindgen]
+impl LifeExport {
+    #[wasm_bindgen(constructor)]
+    pub fn new(rule: &str, pattern: &str) -> Result<LifeExport, JsValue> {
+        let rule: Rule = rule.parse().map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
+        let rows: Vec<&str> = pattern.lines().collect();
+        let pattern = Pattern::from_rows(&rows).map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
+
+        let mut universe = Universe::with_rule(rule);
+        universe.initilaize();
+        universe.set_pattern((0, 0), &pattern);
+
+        Ok(LifeExport {
+            universe,
+            palette: Palette::for_rule(rule),
+        })
+    }
+
+    pub fn png(&self, x: i32, y: i32, width: i32, height: i32, cell_size: u32) -> Result<Vec<u8>, JsValue> {
+        let region = js_region(x, y, width, height, cell_size)?;
+        encode_png(&self.universe, &region, &self.palette).map_err(|err| JsValue::from_str(&format!("{:#?}", err)))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn gif(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: i32,
+        height: i32,
+        cell_size: u32,
+        generations: u32,
+        frame_delay_ms: u32,
+    ) -> Result<Vec<u8>, JsValue> {
+        let region = js_region(x, y, width, height, cell_size)?;
+        encode_gif(&mut self.universe, &region, &self.palette, generations as usize, frame_delay_ms)
+            .map_err(|err| JsValue::from_str(&format!("{:#?}", err)))
+    }
+}
@@ -1,8 +1,14 @@
 use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
     hash::{Hash, Hasher}, 
     ops::{Add, AddAssign, Sub, SubAssign},
     cmp::{Ordering},
+    fmt,
+    str::FromStr,
 };
+use anyhow::{anyhow, bail};
 use bimap::BiMap;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -31,7 +37,7 @@ impl Level {
     }
 
     pub const fn min_coord(self) -> i64 {
-        -(1 << (self.0 - 1)) - 1
+        -(1 << (self.0 - 1))
     }
 
     pub const fn max_coord(self) -> i64 {
@@ -39,7 +45,7 @@ impl Level {
     }
 
     pub const fn coord_range(self) -> std::ops::Range<i64> {
-        self.min_coord()..self.max_coord()
+        self.min_coord()..self.max_coord() + 1
     }
 
     pub fn min_pos(self) -> Position {
@@ -261,11 +267,122 @@ impl Id {
     }
 }
 
-#[repr(u8)]
+// セルの状態 0: 死, 1: 生, 2以上: Generationsルールでの不応期(死にかけ)
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum Cell {
-    Dead = 0u8,
-    Alive = 1u8,
+pub struct Cell(pub u8);
+
+impl Cell {
+    pub const DEAD: Self = Self(0);
+    pub const ALIVE: Self = Self(1);
+
+    pub const fn state(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_alive(self) -> bool {
+        self == Self::ALIVE
+    }
+
+    pub fn is_dead(self) -> bool {
+        self == Self::DEAD
+    }
+}
+
+// Generations系のルール
+// S/B/C記法 (例: "23/3/2" = Conway, "/2/3" = Brian's Brain, "345/2/4" = Star Wars)
+// Bn/Sn/Cn形式 (例: "B3/S23") も受け付ける
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rule {
+    survive: u16, // bit n: 生きているセルの近傍がn個なら生存
+    birth: u16,   // bit n: 死んでいるセルの近傍がn個なら誕生
+    states: u8,   // 状態数 (2ならふつうのライフゲーム)
+}
+
+impl Rule {
+    pub const CONWAY: Self = Self { survive: 0b1100, birth: 0b1000, states: 2 };
+    pub const BRIANS_BRAIN: Self = Self { survive: 0, birth: 0b100, states: 3 };
+    pub const STAR_WARS: Self = Self { survive: 0b11_1000, birth: 0b100, states: 4 };
+
+    pub fn new(survive: &[u8], birth: &[u8], states: u8) -> Self {
+        debug_assert!(states >= 2, "a rule needs at least 2 states");
+        let mask = |counts: &[u8]| counts.iter().fold(0u16, |acc, n| acc | 1 << n);
+        Self {
+            survive: mask(survive),
+            birth: mask(birth),
+            states,
+        }
+    }
+
+    pub const fn states(self) -> u8 {
+        self.states
+    }
+
+    // 近傍の生きているセル数から次の状態を決める
+    pub fn next(self, cell: Cell, alive_neighbors: u32) -> Cell {
+        match cell.0 {
+            0 if self.birth & (1 << alive_neighbors) != 0 => Cell::ALIVE,
+            0 => Cell::DEAD,
+            1 if self.survive & (1 << alive_neighbors) != 0 => Cell::ALIVE,
+            n if n + 1 < self.states => Cell(n + 1),
+            _ => Cell::DEAD,
+        }
+    }
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self::CONWAY
+    }
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let digits = |part: &str| -> anyhow::Result<u16> {
+            part.chars().try_fold(0u16, |acc, c| match c.to_digit(10) {
+                Some(n) if n <= 8 => Ok(acc | 1 << n),
+                _ => Err(anyhow!("invalid neighbor count '{}' in rule '{}'", c, s)),
+            })
+        };
+
+        let parts: Vec<&str> = s.trim().split('/').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            bail!("rule '{}' must look like S/B or S/B/C", s);
+        }
+
+        let (mut survive, mut birth, mut states) = (0, 0, 2);
+        let lettered = parts.iter().any(|p| p.starts_with(|c: char| c.is_ascii_alphabetic()));
+        if lettered {
+            for part in &parts {
+                let (head, tail) = part.split_at(part.chars().next().map_or(0, |c| c.len_utf8()));
+                match head.to_ascii_uppercase().as_str() {
+                    "S" => survive = digits(tail)?,
+                    "B" => birth = digits(tail)?,
+                    "C" | "G" => states = tail.parse().map_err(|_| anyhow!("invalid state count in rule '{}'", s))?,
+                    _ => bail!("unknown section '{}' in rule '{}'", part, s),
+                }
+            }
+        } else {
+            survive = digits(parts[0])?;
+            birth = digits(parts[1])?;
+            if let Some(c) = parts.get(2) {
+                states = c.parse().map_err(|_| anyhow!("invalid state count in rule '{}'", s))?;
+            }
+        }
+
+        if states < 2 {
+            bail!("rule '{}' needs at least 2 states", s);
+        }
+        Ok(Self { survive, birth, states })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let digits = |mask: u16| (0..=8).filter(|n| mask & (1 << n) != 0).map(|n| n.to_string()).collect::<String>();
+        write!(f, "{}/{}/{}", digits(self.survive), digits(self.birth), self.states)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -320,20 +437,13 @@ impl Leaf {
     pub fn new(cell: Cell) -> Self {
         Self(cell)
     }
-
-    fn alive(self) -> bool {
-        match self.0 { // ここself.0なんだ
-            Cell::Dead => false,
-            Cell::Alive => true,
-        }
-    }
 }
 
 impl Node {
     pub fn population(&self) -> u32 {
         match self {
             Node::Inode(ref i) => i.population,
-            Node::Leaf(c) => c.0 as u32,
+            Node::Leaf(c) => !c.0.is_dead() as u32,
         }
     }
 
@@ -344,11 +454,51 @@ impl Node {
         }
     }
 }
+// ノードの内容から決まる128bitハッシュ
+// Idはテーブルへの挿入順なので、セッションやUniverseをまたいで比較するときはこちらを使う
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ContentHash(pub u128);
+
+impl ContentHash {
+    // 128bit FNV-1a
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    fn of_bytes(bytes: &[u8]) -> Self {
+        let hash = bytes
+            .iter()
+            .fold(Self::OFFSET_BASIS, |hash, b| (hash ^ *b as u128).wrapping_mul(Self::PRIME));
+        Self(hash)
+    }
+
+    fn of_leaf(cell: Cell) -> Self {
+        Self::of_bytes(&[0, Level::LEAF_LEVEL.0, cell.state()])
+    }
+
+    fn of_inode(level: Level, children: [Self; 4]) -> Self {
+        let mut bytes = Vec::with_capacity(2 + 4 * 16);
+        bytes.extend_from_slice(&[1, level.0]);
+        for child in children {
+            bytes.extend_from_slice(&child.0.to_le_bytes());
+        }
+        Self::of_bytes(&bytes)
+    }
+}
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
 #[derive(Default)]
 pub struct Universe {
     table: BiMap<Id, Node>,
     root: Option<Id>,
+    previous_root: Option<Id>, // 1世代前のルート (差分表示用)
     generation: usize,
+    rule: Rule,
+    hashes: RefCell<HashMap<Id, ContentHash>>, // node_hashのメモ
 }
 
 impl Universe {
@@ -356,7 +506,17 @@ impl Universe {
         Self {
             table: BiMap::new(),
             root: None, 
+            previous_root: None,
             generation: 0,
+            rule: Rule::default(),
+            hashes: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_rule(rule: Rule) -> Self {
+        Self {
+            rule,
+            ..Self::new()
         }
     }
 
@@ -405,7 +565,7 @@ impl Universe {
                 level: Level::new(1),
                 population: [nw, ne, sw, se]
                     .iter()
-                    .filter(|c| matches!(c.0, Cell::Alive))
+                    .filter(|c| !c.0.is_dead())
                     .count() as u32,
                 result: None,
                 nw: nwx,
@@ -421,7 +581,7 @@ impl Universe {
 
     fn new_empty_tree(&mut self, level: Level) -> Id {
         if level == Level::LEAF_LEVEL {
-            self.new_leaf(Cell::Dead)
+            self.new_leaf(Cell::DEAD)
         } else {
             let child = Self::new_empty_tree(self, level - 1);
             self.new_inode(child, child, child, child)
@@ -579,33 +739,31 @@ fn set_tree_cell(&mut self, tree: Id, pos: impl Into<Position>, state: Cell) ->
             "manual evolution only at level 2 possible"
         );
 
-        let mut all_bits: u16 = 0;
+        // 死にかけの状態も扱うため4x4の状態をそのまま取り出す
+        let mut grid = [[Cell::DEAD; 4]; 4];
         for y in -2..2 {
             for x in -2..2 {
-                all_bits = (all_bits << 1) + self.get_tree_cell(node, (x, y)) as u16;
+                grid[(y + 2) as usize][(x + 2) as usize] = self.get_tree_cell(node, (x, y));
             }
         }
         let (nw, ne, sw, se) = (
-            self.one_gen(all_bits >> 5),
-            self.one_gen(all_bits >> 4),
-            self.one_gen(all_bits >> 1),
-            self.one_gen(all_bits),
+            self.one_gen(&grid, 1, 1),
+            self.one_gen(&grid, 2, 1),
+            self.one_gen(&grid, 1, 2),
+            self.one_gen(&grid, 2, 2),
         );
         self.new_inode(nw, ne, sw, se)
     }
 
-    fn one_gen(&mut self, mut bitmask: u16) -> Id {
-        if bitmask == 0 {
-            return self.new_leaf(Cell::Dead);
-        }
-        let center = (bitmask >> 5) & 1;
-        bitmask &= 0b00000__111_0101_0111; // mask out bits we don't care about
-        let neighbor_count = bitmask.count_ones();
-        if neighbor_count == 3 || (neighbor_count == 2 && center != 0) {
-            self.new_leaf(Cell::Alive)
-        } else {
-            self.new_leaf(Cell::Dead)
-        }
+    fn one_gen(&mut self, grid: &[[Cell; 4]; 4], x: usize, y: usize) -> Id {
+        let block_count = grid[y - 1..=y + 1]
+            .iter()
+            .flat_map(|row| &row[x - 1..=x + 1])
+            .filter(|c| c.is_alive())
+            .count() as u32;
+        let neighbor_count = block_count - grid[y][x].is_alive() as u32;
+        let cell = self.rule.next(grid[y][x], neighbor_count);
+        self.new_leaf(cell)
     }
 
     fn centered_horizontal(&mut self, west: Id, east: Id) -> Id {
@@ -672,6 +830,16 @@ impl Universe {
         self.root = Some(self.set_tree_cell(self.root.unwrap(), pos, cell));
     }
 
+    // パターンを左上がtop_leftになるように置く (パターン中の死んだセルも書き込む)
+    pub fn set_pattern(&mut self, top_left: impl Into<Position>, pattern: &Pattern) {
+        let top_left = top_left.into();
+        for y in 0..pattern.height() {
+            for x in 0..pattern.width() {
+                self.set_cell(top_left + Offset::new(x as i64, y as i64), pattern.get(x, y));
+            }
+        }
+    }
+
     pub fn get_cell(&self, pos: impl Into<Position>) -> Cell {
         let pos = pos.into();
         let root = self.root.unwrap();
@@ -679,8 +847,116 @@ impl Universe {
         if coord_range.contains(&pos.x) && coord_range.contains(&pos.y) {
             self.get_tree_cell(root, pos)
         } else {
-            Cell::Dead
+            Cell::DEAD
+        }
+    }
+
+    pub fn rule(&self) -> Rule {
+        self.rule
+    }
+
+    // ルールを変えると過去の計算結果は使えないのでキャッシュを捨てる
+    pub fn set_rule(&mut self, rule: Rule) {
+        if self.rule == rule {
+            return;
+        }
+        self.rule = rule;
+        self.table = std::mem::take(&mut self.table)
+            .into_iter()
+            .map(|(id, node)| match node {
+                Node::Inode(inode) => (id, Node::Inode(Inode { result: None, ..inode })),
+                leaf => (id, leaf),
+            })
+            .collect();
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    pub fn population(&self) -> u32 {
+        self.root.map_or(0, |root| root.node(self).population())
+    }
+
+    // min以上max未満の範囲にある死んでいないセルを列挙する
+    // 空の部分木は降りずに飛ばす
+    pub fn cells_in(&self, min: impl Into<Position>, max: impl Into<Position>) -> Vec<(Position, Cell)> {
+        let (min, max) = (min.into(), max.into());
+        let mut cells = Vec::new();
+        if let Some(root) = self.root {
+            let level = root.node(self).level();
+            self.collect_cells(root, level.min_pos(), min, max, &mut cells);
         }
+        cells
+    }
+
+    fn collect_cells(&self, tree: Id, top_left: Position, min: Position, max: Position, cells: &mut Vec<(Position, Cell)>) {
+        let node = tree.node(self);
+        if node.population() == 0 {
+            return;
+        }
+        let side = node.level().side_len() as i64;
+        if top_left.x >= max.x || top_left.y >= max.y || top_left.x + side <= min.x || top_left.y + side <= min.y {
+            return;
+        }
+        match node {
+            Node::Leaf(c) => cells.push((top_left, c.0)),
+            Node::Inode(inode) => {
+                let half = side / 2;
+                self.collect_cells(inode.nw, top_left, min, max, cells);
+                self.collect_cells(inode.ne, top_left + Offset::new(half, 0), min, max, cells);
+                self.collect_cells(inode.sw, top_left + Offset::new(0, half), min, max, cells);
+                self.collect_cells(inode.se, top_left + Offset::new(half, half), min, max, cells);
+            }
+        }
+    }
+
+    pub fn root(&self) -> Option<Id> {
+        self.root
+    }
+
+    pub fn previous_root(&self) -> Option<Id> {
+        self.previous_root
+    }
+
+    // 部分木の内容ハッシュ 一度計算したものはメモしておく
+    // Idが指す内容は変わらないのでルールを変えてもメモは有効
+    pub fn node_hash(&self, id: Id) -> ContentHash {
+        if let Some(hash) = self.hashes.borrow().get(&id) {
+            return *hash;
+        }
+        let hash = match id.node(self) {
+            Node::Leaf(c) => ContentHash::of_leaf(c.0),
+            Node::Inode(inode) => ContentHash::of_inode(
+                inode.level,
+                [inode.nw, inode.ne, inode.sw, inode.se].map(|child| self.node_hash(child)),
+            ),
+        };
+        self.hashes.borrow_mut().insert(id, hash);
+        hash
+    }
+
+    // Universe全体の内容ハッシュ
+    // ルートは拡張のたびに大きくなるので、全セルを含む最小の中央の部分木まで縮めてからハッシュを取る
+    // 同じセル配置なら世代数やルートの大きさ、ルールに関係なく同じ値になる
+    pub fn content_hash(&self) -> ContentHash {
+        let Some(root) = self.root else {
+            return ContentHash::of_inode(Level::new(1), [ContentHash::of_leaf(Cell::DEAD); 4]);
+        };
+        let root = root.inode(self);
+        let population = root.population;
+        let (mut level, mut children) = (root.level, [root.nw, root.ne, root.sw, root.se]);
+        while level > 1 {
+            let [nw, ne, sw, se] = children;
+            let inner = [nw.inode(self).se, ne.inode(self).sw, sw.inode(self).ne, se.inode(self).nw];
+            let inner_population: u32 = inner.iter().map(|id| id.node(self).population()).sum();
+            if inner_population != population {
+                break;
+            }
+            level -= 1;
+            children = inner;
+        }
+        ContentHash::of_inode(level, children.map(|child| self.node_hash(child)))
     }
 
     pub fn evolve(&mut self) {
@@ -741,7 +1017,402 @@ impl Universe {
 
         let root = self.root.unwrap();
 
+        self.previous_root = Some(root);
         self.root = Some(self.evolve_tree(root));
         self.generation += 1;
     }
-}
\ No newline at end of file
+}
+// パターン検索
+// 小さなパターン (グライダーや静物など) が8通りの向きのどれかで現れる場所を全部探す
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Orientation {
+    pub flipped: bool,    // 先に左右反転するかどうか
+    pub quarter_turns: u8, // そのあと時計回りに90度回す回数
+}
+
+impl Orientation {
+    pub const IDENTITY: Self = Self { flipped: false, quarter_turns: 0 };
+
+    pub fn all() -> [Self; 8] {
+        let mut all = [Self::IDENTITY; 8];
+        for (i, orientation) in all.iter_mut().enumerate() {
+            *orientation = Self { flipped: i >= 4, quarter_turns: (i % 4) as u8 };
+        }
+        all
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Pattern {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>, // 行優先
+}
+
+impl Pattern {
+    // 生きているセルを囲む最小の矩形に切り詰める
+    pub fn new(width: usize, height: usize, cells: Vec<Cell>) -> anyhow::Result<Self> {
+        if cells.len() != width * height {
+            bail!("pattern has {} cells but should have {}x{}", cells.len(), width, height);
+        }
+        let live: Vec<(usize, usize)> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|&(x, y)| !cells[y * width + x].is_dead())
+            .collect();
+        if live.is_empty() {
+            bail!("pattern has no live cells");
+        }
+        let (min_x, max_x) = (live.iter().map(|p| p.0).min().unwrap(), live.iter().map(|p| p.0).max().unwrap());
+        let (min_y, max_y) = (live.iter().map(|p| p.1).min().unwrap(), live.iter().map(|p| p.1).max().unwrap());
+        let (w, h) = (max_x - min_x + 1, max_y - min_y + 1);
+        let trimmed = (0..h)
+            .flat_map(|y| (0..w).map(move |x| (x, y)))
+            .map(|(x, y)| cells[(y + min_y) * width + x + min_x])
+            .collect();
+        Ok(Self { width: w, height: h, cells: trimmed })
+    }
+
+    // "." が死、"O" か "*" が生、数字はその状態 (例: ".O.", "..O", "OOO" でグライダー)
+    pub fn from_rows(rows: &[&str]) -> anyhow::Result<Self> {
+        let width = rows.iter().map(|row| row.chars().count()).max().unwrap_or(0);
+        let mut cells = Vec::with_capacity(width * rows.len());
+        for row in rows {
+            for c in row.chars().chain(std::iter::repeat('.')).take(width) {
+                let cell = match c {
+                    '.' | 'b' => Cell::DEAD,
+                    'O' | 'o' | '*' => Cell::ALIVE,
+                    d if d.is_ascii_digit() => Cell(d as u8 - b'0'),
+                    _ => bail!("unknown cell '{}' in pattern", c),
+                };
+                cells.push(cell);
+            }
+        }
+        Self::new(width, rows.len(), cells)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Cell {
+        self.cells[y * self.width + x]
+    }
+
+    pub fn oriented(&self, orientation: Orientation) -> Self {
+        let mut pattern = self.clone();
+        if orientation.flipped {
+            pattern = pattern.flip();
+        }
+        for _ in 0..orientation.quarter_turns % 4 {
+            pattern = pattern.rotate();
+        }
+        pattern
+    }
+
+    // 8通りの向きのうち形が異なるものだけを返す
+    pub fn orientations(&self) -> Vec<(Orientation, Self)> {
+        let mut orientations: Vec<(Orientation, Self)> = Vec::new();
+        for orientation in Orientation::all() {
+            let pattern = self.oriented(orientation);
+            if orientations.iter().all(|(_, p)| *p != pattern) {
+                orientations.push((orientation, pattern));
+            }
+        }
+        orientations
+    }
+
+    fn flip(&self) -> Self {
+        let cells = (0..self.height)
+            .flat_map(|y| (0..self.width).rev().map(move |x| (x, y)))
+            .map(|(x, y)| self.get(x, y))
+            .collect();
+        Self { cells, ..self.clone() }
+    }
+
+    // 時計回りに90度
+    fn rotate(&self) -> Self {
+        let (width, height) = (self.height, self.width);
+        let cells = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| self.get(y, self.height - 1 - x))
+            .collect();
+        Self { width, height, cells }
+    }
+
+    // 最初の (行優先で) 生きているセル 候補位置を絞るのに使う
+    fn anchor(&self) -> (usize, usize, Cell) {
+        let i = self.cells.iter().position(|c| !c.is_dead()).unwrap();
+        (i % self.width, i / self.width, self.cells[i])
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PatternMatch {
+    pub position: Position, // 向きを変えたパターンの左上
+    pub orientation: Orientation,
+}
+
+impl Universe {
+    // パターンが (向きを変えたものも含めて) 現れる場所を全部探す
+    // パターンの外接矩形の中身が完全に一致する場所だけを返す
+    // 同じ部分木は同じIdを持つので、部分木ごとの結果をメモして使い回す
+    pub fn find_pattern(&self, pattern: &Pattern) -> Vec<PatternMatch> {
+        let Some(root) = self.root else {
+            return Vec::new();
+        };
+        let top_left = root.node(self).level().min_pos();
+
+        let mut matches = Vec::new();
+        for (orientation, oriented) in pattern.orientations() {
+            let mut memo = HashMap::new();
+            for offset in self.node_matches(root, &oriented, &mut memo).iter() {
+                matches.push(PatternMatch {
+                    position: top_left + *offset,
+                    orientation,
+                });
+            }
+        }
+        matches.sort_by_key(|m| (m.position.y, m.position.x));
+        matches
+    }
+
+    // 部分木の中に収まる一致の位置 (部分木の左上からのオフセット)
+    fn node_matches(&self, tree: Id, pattern: &Pattern, memo: &mut HashMap<Id, Rc<Vec<Offset>>>) -> Rc<Vec<Offset>> {
+        if let Some(found) = memo.get(&tree) {
+            return found.clone();
+        }
+
+        let node = tree.node(self);
+        let side = node.level().side_len() as i64;
+        let (w, h) = (pattern.width as i64, pattern.height as i64);
+        let mut found = Vec::new();
+
+        if node.population() > 0 && side >= w && side >= h {
+            match node {
+                Node::Leaf(c) => {
+                    if c.0 == pattern.get(0, 0) {
+                        found.push(Offset::new(0, 0));
+                    }
+                }
+                Node::Inode(inode) => {
+                    let half = side / 2;
+                    let children = [
+                        (inode.nw, Offset::new(0, 0)),
+                        (inode.ne, Offset::new(half, 0)),
+                        (inode.sw, Offset::new(0, half)),
+                        (inode.se, Offset::new(half, half)),
+                    ];
+                    // 子の中に収まるもの
+                    for (child, corner) in children {
+                        for offset in self.node_matches(child, pattern, memo).iter() {
+                            found.push(Offset::new(offset.dx + corner.dx, offset.dy + corner.dy));
+                        }
+                    }
+                    // 子の境界をまたぐもの
+                    found.extend(self.straddling_matches(tree, side, pattern));
+                }
+            }
+        }
+
+        let found = Rc::new(found);
+        memo.insert(tree, found.clone());
+        found
+    }
+
+    // 中央の縦線か横線をまたぐ一致
+    // 境界付近の生きているセルからパターンの左上の候補を逆算して確かめる
+    fn straddling_matches(&self, tree: Id, side: i64, pattern: &Pattern) -> Vec<Offset> {
+        let half = side / 2;
+        let (w, h) = (pattern.width as i64, pattern.height as i64);
+        let (ax, ay, anchor) = pattern.anchor();
+        let (ax, ay) = (ax as i64, ay as i64);
+
+        let mut cells = Vec::new();
+        self.collect_cells(tree, Position::ORIGIN, (half - w + 1, 0).into(), (half + w - 1, side).into(), &mut cells);
+        self.collect_cells(tree, Position::ORIGIN, (0, half - h + 1).into(), (side, half + h - 1).into(), &mut cells);
+
+        let mut candidates: Vec<(i64, i64)> = cells
+            .into_iter()
+            .filter(|(_, cell)| *cell == anchor)
+            .map(|(pos, _)| (pos.x - ax, pos.y - ay))
+            .filter(|&(x, y)| x >= 0 && y >= 0 && x + w <= side && y + h <= side)
+            .filter(|&(x, y)| (x < half && x + w > half) || (y < half && y + h > half))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+
+        candidates
+            .into_iter()
+            .filter(|&(x, y)| {
+                (0..h).all(|dy| (0..w).all(|dx| self.node_cell(tree, x + dx, y + dy) == pattern.get(dx as usize, dy as usize)))
+            })
+            .map(|(x, y)| Offset::new(x, y))
+            .collect()
+    }
+
+    // 部分木の左上を原点とした座標でセルを取得する
+    fn node_cell(&self, tree: Id, x: i64, y: i64) -> Cell {
+        match tree.node(self) {
+            Node::Leaf(c) => c.0,
+            Node::Inode(inode) if inode.population == 0 => Cell::DEAD,
+            Node::Inode(inode) => {
+                let half = inode.level.side_len() as i64 / 2;
+                match (x >= half, y >= half) {
+                    (false, false) => self.node_cell(inode.nw, x, y),
+                    (true, false) => self.node_cell(inode.ne, x - half, y),
+                    (false, true) => self.node_cell(inode.sw, x, y - half),
+                    (true, true) => self.node_cell(inode.se, x - half, y - half),
+                }
+            }
+        }
+    }
+}
+
+// 世代間の差分
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CellDiff {
+    pub born: Vec<Position>, // 生きていなかったのが生きるようになったセル
+    pub died: Vec<Position>, // 生きていたのが生きていなくなったセル
+}
+
+// 差分を取るときに、ある領域を片方の木がどう覆っているか
+#[derive(Debug, Copy, Clone)]
+enum Cover {
+    Node(Id),  // ちょうどこのノードが領域を覆っている
+    Empty,     // 木の外側
+    Partial,   // 領域と木が一部だけ重なっている (領域の方が大きいか、境目がずれている)
+}
+
+impl Universe {
+    // 2つのルートを比べて、min以上max未満の範囲で生まれたセルと死んだセルを返す
+    // ルートのレベルが違っても良い 同じIdの部分木は比べずに飛ばす
+    pub fn diff(&self, before: Id, after: Id, min: impl Into<Position>, max: impl Into<Position>) -> CellDiff {
+        let (min, max) = (min.into(), max.into());
+        let level = before.node(self).level().max(after.node(self).level());
+        let top_left = level.min_pos();
+        let (a, b) = (self.cover(before, top_left, level), self.cover(after, top_left, level));
+
+        let mut diff = CellDiff::default();
+        self.diff_region((before, after), top_left, level, a, b, (min, max), &mut diff);
+        diff
+    }
+
+    // 直前のevolveでの差分
+    pub fn last_step_diff(&self, min: impl Into<Position>, max: impl Into<Position>) -> CellDiff {
+        match (self.previous_root, self.root) {
+            (Some(before), Some(after)) => self.diff(before, after, min, max),
+            _ => CellDiff::default(),
+        }
+    }
+
+    // 原点中心の木rootが、左上top_leftでレベルlevelの領域をどう覆うか
+    fn cover(&self, root: Id, top_left: Position, level: Level) -> Cover {
+        let root_level = root.node(self).level();
+        let root_top_left = root_level.min_pos();
+        let (side, root_side) = (level.side_len() as i64, root_level.side_len() as i64);
+
+        if top_left.x >= root_top_left.x + root_side
+            || top_left.y >= root_top_left.y + root_side
+            || top_left.x + side <= root_top_left.x
+            || top_left.y + side <= root_top_left.y
+        {
+            return Cover::Empty;
+        }
+        if level > root_level || (level == root_level && top_left != root_top_left) {
+            return Cover::Partial;
+        }
+
+        let (mut tree, mut tree_top_left) = (root, root_top_left);
+        while tree.node(self).level() > level {
+            let inode = tree.inode(self);
+            let half = inode.level.side_len() as i64 / 2;
+            let (east, south) = (top_left.x >= tree_top_left.x + half, top_left.y >= tree_top_left.y + half);
+            tree = match (east, south) {
+                (false, false) => inode.nw,
+                (true, false) => inode.ne,
+                (false, true) => inode.sw,
+                (true, true) => inode.se,
+            };
+            tree_top_left += Offset::new(if east { half } else { 0 }, if south { half } else { 0 });
+        }
+        Cover::Node(tree)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn diff_region(
+        &self,
+        roots: (Id, Id),
+        top_left: Position,
+        level: Level,
+        before: Cover,
+        after: Cover,
+        viewport: (Position, Position),
+        diff: &mut CellDiff,
+    ) {
+        let (min, max) = viewport;
+        let side = level.side_len() as i64;
+        if top_left.x >= max.x || top_left.y >= max.y || top_left.x + side <= min.x || top_left.y + side <= min.y {
+            return;
+        }
+        let is_empty = |cover: Cover| match cover {
+            Cover::Node(id) => id.node(self).population() == 0,
+            Cover::Empty => true,
+            Cover::Partial => false,
+        };
+        match (before, after) {
+            (Cover::Node(a), Cover::Node(b)) if a == b => return,
+            _ if is_empty(before) && is_empty(after) => return,
+            _ => {}
+        }
+
+        if level == Level::LEAF_LEVEL {
+            let cell = |cover: Cover| match cover {
+                Cover::Node(id) => id.leaf(self).0,
+                _ => Cell::DEAD,
+            };
+            match (cell(before).is_alive(), cell(after).is_alive()) {
+                (false, true) => diff.born.push(top_left),
+                (true, false) => diff.died.push(top_left),
+                _ => {}
+            }
+            return;
+        }
+
+        let half = side / 2;
+        let quadrants = [
+            (Quadrant::NorthWest, Offset::new(0, 0)),
+            (Quadrant::NorthEast, Offset::new(half, 0)),
+            (Quadrant::SouthWest, Offset::new(0, half)),
+            (Quadrant::SouthEast, Offset::new(half, half)),
+        ];
+        for (quadrant, corner) in quadrants {
+            let child_top_left = top_left + corner;
+            let child = |cover: Cover, root: Id| match cover {
+                Cover::Node(id) => {
+                    let inode = id.inode(self);
+                    Cover::Node(match quadrant {
+                        Quadrant::NorthWest => inode.nw,
+                        Quadrant::NorthEast => inode.ne,
+                        Quadrant::SouthWest => inode.sw,
+                        Quadrant::SouthEast => inode.se,
+                    })
+                }
+                Cover::Empty => Cover::Empty,
+                Cover::Partial => self.cover(root, child_top_left, level - 1),
+            };
+            self.diff_region(
+                roots,
+                child_top_left,
+                level - 1,
+                child(before, roots.0),
+                child(after, roots.1),
+                viewport,
+                diff,
+            );
+        }
+    }
+}
@@ -4,12 +4,17 @@ use wasm_bindgen::JsValue;
 
 #[macro_use]
 mod browser;
-mod engine;
-mod boid;
+pub mod engine;
+pub mod parameter;
+pub mod boid;
 mod analog_clock;
-mod hashlife;
+pub mod hashlife;
+pub mod export;
+mod life;
 
 use crate::boid::boid::Boid;
+use crate::boid::boid3d::Boid3d;
+use crate::hashlife::Rule;
 
 // This is like the `main` function, except for JavaScript.
 #[wasm_bindgen(start)]
@@ -44,19 +49,6 @@ pub fn test1() -> Result<(), JsValue> {
     ctx_2d.set_fill_style(&JsValue::from_str("rgba(0, 0, 0, 0.2)"));
     ctx_2d.fill_rect(0.0, 0.0, canvas.width().into(), canvas.height().into());
 
-    // // windows resizeイベントを登録
-    // let window = browser::window().map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
-    // let closure = Closure::<dyn FnMut()>::wrap(Box::new(move|| {
-    //     log!("Window resized!");
-    //     browser::set_canvas_fullscreen().map_err(|err| JsValue::from_str(&format!("{:#?}", err))).unwrap();
-    // }));
-
-    // window
-    //     .add_event_listener_with_callback("resize", closure.as_ref().unchecked_ref())
-    //     .map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
-
-    // closure.forget();
-
     // debug
     log!("デバッグ！");
     let renderer = engine::Renderer2d{context: ctx_2d};
@@ -84,25 +76,8 @@ pub fn test2() -> Result<(), JsValue> {
 }
 
 #[wasm_bindgen]
-pub fn boid_start() -> Result<(), JsValue> {
-    log!("boid called!");
-
-    // パラメータのセットアップ
-    let param_check = browser::check_parameter_ui().map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
-    if param_check {
-        let param_list: Vec<&str> = vec![
-            "boidcount",
-            "separateforce",
-            "alignforce",
-            "cohesionforce",
-            "separatedistance",
-            "aligndistance",
-            "cohesiondistance",
-        ];
-        for name in param_list {
-            let param = browser::set_parameter_ui(name).map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
-        }
-    }
+pub fn boid_start(seed: u32) -> Result<(), JsValue> {
+    log!("boid called! seed: {}", seed);
 
     browser::set_canvas_fullscreen().map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
 
@@ -123,7 +98,52 @@ pub fn boid_start() -> Result<(), JsValue> {
     let height = canvas.height();
 
     browser::spawn_local(async move{
-        let game = Boid::new(width, height);
+        let game = Boid::new(width, height, seed as u64);
+
+        engine::GameLoop::start(game)
+            .await
+            .expect("Failed to start game");
+    });
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn boid3d_start(seed: u32) -> Result<(), JsValue> {
+    log!("boid3d called! seed: {}", seed);
+
+    browser::set_canvas_fullscreen().map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
+
+    let canvas = browser::canvas().map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
+    let width = canvas.width();
+    let height = canvas.height();
+
+    browser::spawn_local(async move{
+        let game = Boid3d::new(width, height, seed as u64);
+
+        engine::GameLoop::start(game)
+            .await
+            .expect("Failed to start game");
+    });
+
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn life_start(rule: &str, view: &str, seed: u32) -> Result<(), JsValue> {
+    log!("life called! rule: {}, view: {}, seed: {}", rule, view, seed);
+
+    let rule: Rule = rule.parse().map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
+    let view = life::LifeView::from_name(view);
+
+    browser::set_canvas_fullscreen().map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
+
+    let canvas = browser::canvas().map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
+    let width = canvas.width();
+    let height = canvas.height();
+
+    browser::spawn_local(async move{
+        let game = life::Life::new(width, height, rule, view, seed as u64);
 
         engine::GameLoop::start(game)
             .await
@@ -139,22 +159,6 @@ pub fn boid_start() -> Result<(), JsValue> {
 //#[wasm_bindgen]
 pub fn run() -> Result<(), JsValue> {
     log!("デバッグのrunが実行されました！！！");
-    // パラメータのセットアップ
-    let param_check = browser::check_parameter_ui().map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
-    if param_check {
-        let param_list: Vec<&str> = vec![
-            "boidcount",
-            "separateforce",
-            "alignforce",
-            "cohesionforce",
-            "separatedistance",
-            "aligndistance",
-            "cohesiondistance",
-        ];
-        for name in param_list {
-            let param = browser::set_parameter_ui(name).map_err(|err| JsValue::from_str(&format!("{:#?}", err)))?;
-        }
-    }
 
     Ok(())
 }
\ No newline at end of file
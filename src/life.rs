@@ -0,0 +1,138 @@
+// ライフゲーム (hashlife + Generationsルール)
+use anyhow::Result;
+use async_trait::async_trait;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::engine::{
+    Game, Point, Renderer2d,
+};
+use crate::hashlife::{Cell, Position, Rule, Universe};
+
+const BORN_COLOR: &str = "rgba(0, 255, 0, 0.9)";
+const DIED_COLOR: &str = "rgba(255, 0, 0, 0.9)";
+const SURVIVED_COLOR: &str = "rgba(200, 200, 200, 0.6)";
+
+// 描画モード
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LifeView {
+    States, // 状態ごとに色分け
+    Diff,   // 直前の世代から生まれた/死んだ/生き残ったセルを色分け
+}
+
+impl LifeView {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "diff" => LifeView::Diff,
+            _ => LifeView::States,
+        }
+    }
+}
+
+const SOUP_SIZE: i64 = 64;     // 初期配置するランダムスープの一辺
+const SOUP_DENSITY: f64 = 0.35; // スープ中の生きているセルの割合
+
+pub struct Life {
+    universe: Universe,
+    width: u32,  // 画面の幅
+    height: u32, // 画面の高さ
+    cell_size: f64,
+    rule: Rule,
+    view: LifeView,
+    seed: u64, // 初期配置のスープのシード
+}
+
+impl Life {
+    pub fn new(width: u32, height: u32, rule: Rule, view: LifeView, seed: u64) -> Self {
+        Self {
+            universe: Universe::with_rule(rule),
+            width,
+            height,
+            cell_size: 4.0,
+            rule,
+            view,
+            seed,
+        }
+    }
+
+    // 画面に映っている範囲 (セル座標、原点が画面中央)
+    fn viewport(&self) -> (Position, Position) {
+        let half_w = (self.width as f64 / 2.0 / self.cell_size).ceil() as i64;
+        let half_h = (self.height as f64 / 2.0 / self.cell_size).ceil() as i64;
+        (Position::new(-half_w, -half_h), Position::new(half_w, half_h))
+    }
+
+    fn to_screen(&self, pos: Position) -> Point {
+        Point::new(
+            self.width as f64 / 2.0 + pos.x as f64 * self.cell_size,
+            self.height as f64 / 2.0 + pos.y as f64 * self.cell_size,
+        )
+    }
+}
+
+// 状態ごとの色 生きているセルは緑、死にかけのセルは青から暗くなっていく
+pub fn cell_color(cell: Cell, states: u8) -> String {
+    match cell.state() {
+        0 => "rgba(0, 0, 0, 0)".to_string(),
+        1 => "rgba(0, 255, 0, 0.9)".to_string(),
+        n => {
+            let t = (n - 1) as f64 / (states - 1) as f64;
+            format!("rgba(0, {}, 255, {:.2})", (160.0 * (1.0 - t)) as u8, 0.9 - 0.7 * t)
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Game for Life {
+    async fn initialize(&self) -> Result<Box<dyn Game>> {
+        log!("Life initialize: rule {}, seed {}", self.rule, self.seed);
+        let mut life = Life::new(self.width, self.height, self.rule, self.view, self.seed);
+        life.universe.initilaize();
+
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        for y in -SOUP_SIZE / 2..SOUP_SIZE / 2 {
+            for x in -SOUP_SIZE / 2..SOUP_SIZE / 2 {
+                if rng.gen_bool(SOUP_DENSITY) {
+                    life.universe.set_cell((x, y), Cell::ALIVE);
+                }
+            }
+        }
+
+        Ok(Box::new(life))
+    }
+
+    fn update(&mut self, _dt: f64) {
+        self.universe.evolve();
+    }
+
+    fn draw(&self, renderer: &Renderer2d) {
+        renderer.clear();
+        let (min, max) = self.viewport();
+        match self.view {
+            LifeView::States => {
+                let states = self.rule.states();
+                for (pos, cell) in self.universe.cells_in(min, max) {
+                    renderer.rect(self.to_screen(pos), self.cell_size, self.cell_size, &cell_color(cell, states));
+                }
+            }
+            LifeView::Diff => {
+                // 生き残ったセルを描いてから、生まれたセルと死んだセルを上から描く
+                for (pos, _) in self.universe.cells_in(min, max).into_iter().filter(|(_, cell)| cell.is_alive()) {
+                    renderer.rect(self.to_screen(pos), self.cell_size, self.cell_size, SURVIVED_COLOR);
+                }
+                let diff = self.universe.last_step_diff(min, max);
+                for pos in diff.born {
+                    renderer.rect(self.to_screen(pos), self.cell_size, self.cell_size, BORN_COLOR);
+                }
+                for pos in diff.died {
+                    renderer.rect(self.to_screen(pos), self.cell_size, self.cell_size, DIED_COLOR);
+                }
+            }
+        }
+    }
+
+    // 原点はいつも画面中央なので、映す範囲が変わるだけ
+    fn on_resize(&mut self, width: u32, height: u32) {
+        self.width = width;
+        self.height = height;
+    }
+}
@@ -0,0 +1,225 @@
+// ゲームごとのパラメータの宣言
+// 名前・範囲・既定値と、型つきの読み書きの関数をまとめて持つ
+// エンジンはこれを見てパネルを組み立て、変更を型つきの値にしてゲームに渡す
+
+// パネルに出す型
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParameterKind {
+    Number,
+    Integer,
+    Toggle,
+    Choice(&'static [&'static str]), // 選択肢の名前 (値は添字)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ParameterValue {
+    Number(f64),
+    Integer(u64),
+    Toggle(bool),
+    Choice(usize),
+}
+
+impl ParameterValue {
+    // パネルのinputに入れる値
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            ParameterValue::Number(value) => value,
+            ParameterValue::Integer(value) => value as f64,
+            ParameterValue::Toggle(value) => if value { 1.0 } else { 0.0 },
+            ParameterValue::Choice(index) => index as f64,
+        }
+    }
+}
+
+// パネルを作るための情報 (ゲームの型に依存しない部分)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ParameterSpec {
+    pub name: &'static str, // inputのidに使う (param-slider-{name}, param-input-{name})
+    pub label: &'static str,
+    pub min: f64,
+    pub max: f64,
+    pub step: f64,
+    pub default: f64,
+    pub kind: ParameterKind,
+}
+
+impl ParameterSpec {
+    // inputの値を範囲内に収めて型つきの値にする
+    pub fn value_from(&self, raw: f64) -> ParameterValue {
+        let raw = if raw.is_nan() { self.default } else { raw.clamp(self.min, self.max) };
+        match self.kind {
+            ParameterKind::Number => ParameterValue::Number(raw),
+            ParameterKind::Integer => ParameterValue::Integer(raw.round() as u64),
+            ParameterKind::Toggle => ParameterValue::Toggle(raw >= 0.5),
+            ParameterKind::Choice(_) => ParameterValue::Choice(raw.round() as usize),
+        }
+    }
+
+    // "(0: Off, 1: On)" のような値の説明をつけたラベル
+    pub fn display_label(&self) -> String {
+        match self.kind {
+            ParameterKind::Toggle => format!("{} (0: Off, 1: On):", self.label),
+            ParameterKind::Choice(options) => {
+                let options: Vec<String> = options.iter().enumerate().map(|(i, option)| format!("{}: {}", i, option)).collect();
+                format!("{} ({}):", self.label, options.join(", "))
+            }
+            _ => format!("{}:", self.label),
+        }
+    }
+}
+
+// 型ごとの読み書き
+pub enum Accessor<G> {
+    Number(fn(&G) -> f64, fn(&mut G, f64)),
+    Integer(fn(&G) -> u64, fn(&mut G, u64)),
+    Toggle(fn(&G) -> bool, fn(&mut G, bool)),
+    Choice(&'static [&'static str], fn(&G) -> usize, fn(&mut G, usize)),
+}
+
+// ゲームGのパラメータ1つ
+pub struct Parameter<G> {
+    pub name: &'static str,
+    pub label: &'static str,
+    pub min: f64,
+    pub max: f64,
+    pub step: f64,
+    pub default: f64,
+    pub access: Accessor<G>,
+}
+
+impl<G> Parameter<G> {
+    pub const fn number(
+        name: &'static str,
+        label: &'static str,
+        (min, max, step): (f64, f64, f64),
+        default: f64,
+        get: fn(&G) -> f64,
+        set: fn(&mut G, f64),
+    ) -> Self {
+        Self { name, label, min, max, step, default, access: Accessor::Number(get, set) }
+    }
+
+    pub const fn integer(
+        name: &'static str,
+        label: &'static str,
+        (min, max): (f64, f64),
+        default: f64,
+        get: fn(&G) -> u64,
+        set: fn(&mut G, u64),
+    ) -> Self {
+        Self { name, label, min, max, step: 1.0, default, access: Accessor::Integer(get, set) }
+    }
+
+    pub const fn toggle(name: &'static str, label: &'static str, default: bool, get: fn(&G) -> bool, set: fn(&mut G, bool)) -> Self {
+        let default = if default { 1.0 } else { 0.0 };
+        Self { name, label, min: 0.0, max: 1.0, step: 1.0, default, access: Accessor::Toggle(get, set) }
+    }
+
+    pub const fn choice(
+        name: &'static str,
+        label: &'static str,
+        options: &'static [&'static str],
+        default: usize,
+        get: fn(&G) -> usize,
+        set: fn(&mut G, usize),
+    ) -> Self {
+        let max = (options.len() - 1) as f64;
+        Self { name, label, min: 0.0, max, step: 1.0, default: default as f64, access: Accessor::Choice(options, get, set) }
+    }
+
+    pub fn spec(&self) -> ParameterSpec {
+        let kind = match self.access {
+            Accessor::Number(..) => ParameterKind::Number,
+            Accessor::Integer(..) => ParameterKind::Integer,
+            Accessor::Toggle(..) => ParameterKind::Toggle,
+            Accessor::Choice(options, ..) => ParameterKind::Choice(options),
+        };
+        ParameterSpec {
+            name: self.name,
+            label: self.label,
+            min: self.min,
+            max: self.max,
+            step: self.step,
+            default: self.default,
+            kind,
+        }
+    }
+
+    pub fn get(&self, game: &G) -> ParameterValue {
+        match self.access {
+            Accessor::Number(get, _) => ParameterValue::Number(get(game)),
+            Accessor::Integer(get, _) => ParameterValue::Integer(get(game)),
+            Accessor::Toggle(get, _) => ParameterValue::Toggle(get(game)),
+            Accessor::Choice(_, get, _) => ParameterValue::Choice(get(game)),
+        }
+    }
+
+    // 型が違う値は無視する
+    pub fn set(&self, game: &mut G, value: ParameterValue) {
+        match (&self.access, value) {
+            (Accessor::Number(_, set), ParameterValue::Number(value)) => set(game, value),
+            (Accessor::Integer(_, set), ParameterValue::Integer(value)) => set(game, value),
+            (Accessor::Toggle(_, set), ParameterValue::Toggle(value)) => set(game, value),
+            (Accessor::Choice(_, _, set), ParameterValue::Choice(index)) => set(game, index),
+            (_, value) => log!("Parameter {} got a value of the wrong type: {:?}", self.name, value),
+        }
+    }
+}
+
+// パネルでパラメータが変わったときにゲームに渡すもの (indexはGame::parametersの並び)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ParameterChange {
+    pub index: usize,
+    pub value: ParameterValue,
+}
+
+// Game::parameters / parameter_values / handle_parameter の中身
+// ゲームは自分のパラメータの表を渡すだけでよい
+pub fn specs<G>(table: &[Parameter<G>]) -> Vec<ParameterSpec> {
+    table.iter().map(Parameter::spec).collect()
+}
+
+pub fn values<G>(table: &[Parameter<G>], game: &G) -> Vec<ParameterValue> {
+    table.iter().map(|parameter| parameter.get(game)).collect()
+}
+
+pub fn apply<G>(table: &[Parameter<G>], game: &mut G, change: &ParameterChange) {
+    match table.get(change.index) {
+        Some(parameter) => parameter.set(game, change.value),
+        None => log!("Unknown parameter index: {}", change.index),
+    }
+}
+
+// 組み込みのプリセット valuesに無いパラメータはゲームの既定値にする (シードはそのまま)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Preset {
+    pub name: &'static str,
+    pub values: &'static [(&'static str, f64)],
+}
+
+impl Preset {
+    // defaultsはparametersと同じ並びの既定値 (Game::parameter_defaults)
+    pub fn query(&self, parameters: &[ParameterSpec], defaults: &[ParameterValue]) -> String {
+        let defaults = parameters.iter().enumerate().map(|(i, spec)| defaults.get(i).map_or(spec.default, ParameterValue::as_f64));
+        encode_query(parameters.iter().zip(defaults).filter(|(spec, _)| spec.name != "seed").map(|(spec, default)| {
+            let value = self.values.iter().find(|(name, _)| *name == spec.name).map_or(default, |(_, value)| *value);
+            (spec.name, value.to_string())
+        }))
+    }
+}
+
+// プリセットやURLに書く形 (name=value&name=value)
+// 名前は英小文字、値は数値だけなのでエスケープはしない
+pub fn encode_query<'a>(values: impl Iterator<Item = (&'a str, String)>) -> String {
+    values.map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("&")
+}
+
+// 数値として読めないものは飛ばす
+pub fn decode_query(query: &str) -> Vec<(String, f64)> {
+    query
+        .trim_start_matches('?')
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .filter_map(|(name, value)| Some((name.to_string(), value.parse().ok()?)))
+        .collect()
+}
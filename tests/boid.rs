@@ -0,0 +1,599 @@
+use rust_webpack_template::boid::boid::{heat_rgb, hsv_to_rgb, Boid, BoidParameters, BoundaryMode, Integrator, PointerTool, Species, SteeringModel, FORCE_UNIT};
+use rust_webpack_template::boid::analytics::FlockMetrics;
+use rust_webpack_template::boid::boid3d::{Boid3d, Camera};
+use rust_webpack_template::boid::grid::{SpatialGrid, SpatialGrid3d};
+use rust_webpack_template::boid::obstacle::Obstacle;
+use rust_webpack_template::boid::trajectory::run_headless;
+use rust_webpack_template::engine::{Game, Point, PointerEvent, PointerKind, Vec3};
+use rust_webpack_template::parameter::{decode_query, ParameterChange, ParameterValue};
+
+const DT: f64 = 1.0 / 60.0;
+
+fn trajectory(seed: u64, steps: usize) -> Vec<Point> {
+    let parameters = BoidParameters {
+        boid_count: 0,
+        predator_count: 0,
+        seed,
+        ..BoidParameters::default()
+    };
+    let mut boid = Boid::with_parameters(400, 300, parameters);
+    boid.update_boid_count(200);
+    boid.update_predator_count(3);
+    for _ in 0..steps {
+        boid.update(DT);
+    }
+    boid.positions().collect()
+}
+
+#[test]
+fn same_seed_gives_same_trajectory() {
+    assert_eq!(trajectory(42, 200), trajectory(42, 200));
+}
+
+#[test]
+fn different_seed_gives_different_trajectory() {
+    assert_ne!(trajectory(1, 10), trajectory(2, 10));
+}
+
+#[test]
+fn wrapped_grid_finds_neighbors_across_the_edge() {
+    let positions = [Point::new(2.0, 50.0), Point::new(397.0, 50.0), Point::new(200.0, 50.0)];
+    let mut grid = SpatialGrid::new();
+
+    grid.rebuild(positions.iter().copied(), 400.0, 300.0, 30.0, true);
+    let found: Vec<usize> = grid.neighbors(positions[0], 10.0).collect();
+    assert!(found.contains(&1));
+    assert!(!found.contains(&2));
+
+    grid.rebuild(positions.iter().copied(), 400.0, 300.0, 30.0, false);
+    let found: Vec<usize> = grid.neighbors(positions[0], 10.0).collect();
+    assert!(!found.contains(&1));
+}
+
+#[test]
+fn wrapped_3d_grid_finds_neighbors_across_the_edge() {
+    let positions = [Vec3::new(-298.0, 0.0, 10.0), Vec3::new(297.0, 0.0, 10.0), Vec3::new(0.0, 0.0, 10.0)];
+    let mut grid = SpatialGrid3d::new();
+
+    grid.rebuild(positions.iter().copied(), 600.0, 30.0, true);
+    let found: Vec<usize> = grid.neighbors(positions[0], 10.0).collect();
+    assert!(found.contains(&1));
+    assert!(!found.contains(&2));
+
+    grid.rebuild(positions.iter().copied(), 600.0, 30.0, false);
+    let found: Vec<usize> = grid.neighbors(positions[0], 10.0).collect();
+    assert!(!found.contains(&1));
+}
+
+#[test]
+fn cohesion_pulls_across_the_wrapped_edge() {
+    // 端をはさんで10pxの2つは、画面を横切らずに端の方へ寄る
+    let parameters = BoidParameters {
+        boid_count: 0,
+        separate_distance: 1.0,
+        ..BoidParameters::default()
+    };
+    let mut boid = Boid::with_parameters(400, 300, parameters);
+    boid.add_agent(Species::Prey, Point::new(5.0, 150.0), Point::new(0.0, 0.0));
+    boid.add_agent(Species::Prey, Point::new(395.0, 150.0), Point::new(0.0, 0.0));
+    boid.update(DT);
+    let accelerations: Vec<Point> = boid.accelerations().collect();
+    assert!(accelerations[0].x < 0.0 && accelerations[1].x > 0.0, "{:?}", accelerations);
+    assert!(accelerations.iter().all(|a| a.y.abs() < 1e-6));
+}
+
+#[test]
+fn reynolds_caps_the_flocking_force_and_direct_does_not() {
+    // 真ん中の1つは上へ、まわりの12個はそろって右へ進んでいる (整列だけが効く)
+    let run = |steering_model: SteeringModel| {
+        let parameters = BoidParameters {
+            boid_count: 0,
+            steering_model,
+            alignment_force: 0.5,
+            cohesion_force: 0.0,
+            ..BoidParameters::default()
+        };
+        let mut boid = Boid::with_parameters(400, 300, parameters);
+        boid.add_agent(Species::Prey, Point::new(200.0, 150.0), Point::new(0.0, -100.0));
+        for k in 0..12 {
+            let angle = k as f64 * std::f64::consts::TAU / 12.0;
+            let position = Point::new(200.0 + 35.0 * angle.cos(), 150.0 + 35.0 * angle.sin());
+            boid.add_agent(Species::Prey, position, Point::new(100.0, 0.0));
+        }
+        boid.update(DT);
+        let a = boid.accelerations().next().unwrap();
+        a.x.hypot(a.y)
+    };
+    let limit = BoidParameters::default().max_force * FORCE_UNIT;
+    let reynolds = run(SteeringModel::Reynolds);
+    assert!(reynolds > 0.0 && reynolds <= limit * (1.0 + 1e-6), "{} > {}", reynolds, limit);
+    let direct = run(SteeringModel::Direct);
+    assert!(direct > limit, "{} <= {}", direct, limit);
+}
+
+#[test]
+fn neighbours_behind_are_outside_a_narrow_view() {
+    // 右へ進む1つの真後ろ20pxに、同じ向きに進むものがいる
+    let run = |view_angle: f64| {
+        let parameters = BoidParameters { boid_count: 0, view_angle, ..BoidParameters::default() };
+        let mut boid = Boid::with_parameters(400, 300, parameters);
+        boid.add_agent(Species::Prey, Point::new(200.0, 150.0), Point::new(100.0, 0.0));
+        boid.add_agent(Species::Prey, Point::new(180.0, 150.0), Point::new(100.0, 0.0));
+        boid.update(DT);
+        let acceleration = boid.accelerations().next().unwrap();
+        acceleration
+    };
+    // 見えなければ何の力も掛からない
+    let narrow = run(270.0);
+    assert!(narrow.x.abs() < 1e-6 && narrow.y.abs() < 1e-6, "{:?}", narrow);
+    // 全方向が見えれば、分離で前に押し出される
+    let all_around = run(360.0);
+    assert!(all_around.x > 0.0 && all_around.y.abs() < 1e-6, "{:?}", all_around);
+}
+
+#[test]
+fn steering_does_not_depend_on_agent_order() {
+    // 近くに集まった同じエージェントを、逆の順に置いても同じ力が掛かる
+    let agents: Vec<(Point, Point)> = (0..10)
+        .map(|k| {
+            let k = k as f64;
+            (Point::new(180.0 + 7.0 * k, 140.0 + 13.0 * (k * 1.7).sin()), Point::new(60.0 * (k * 0.9).cos(), 60.0 * (k * 0.9).sin()))
+        })
+        .collect();
+    let run = |agents: &[(Point, Point)]| {
+        let mut boid = Boid::with_parameters(400, 300, BoidParameters { boid_count: 0, ..BoidParameters::default() });
+        for &(position, velocity) in agents {
+            boid.add_agent(Species::Prey, position, velocity);
+        }
+        boid.update(DT);
+        boid.accelerations().collect::<Vec<Point>>()
+    };
+    let forward = run(&agents);
+    let reversed: Vec<(Point, Point)> = agents.iter().rev().copied().collect();
+    let mut backward = run(&reversed);
+    backward.reverse();
+    assert!(forward.iter().any(|a| a.x != 0.0 || a.y != 0.0));
+    for (a, b) in forward.iter().zip(&backward) {
+        // 和を取る順が変わるぶんの丸め誤差 (f32) は許す
+        let close = |a: f64, b: f64| (a - b).abs() <= 1e-4 * a.abs().max(1.0);
+        assert!(close(a.x, b.x) && close(a.y, b.y), "{:?} != {:?}", a, b);
+    }
+}
+
+#[test]
+fn attractor_pulls_and_repels_only_within_its_radius() {
+    // 止まっている2つのうち、1つ目だけがポインタ (右に60px) から半径内にいる
+    let pointer = Point::new(260.0, 150.0);
+    let run = |button: i16, shift: bool| {
+        let parameters = BoidParameters { boid_count: 0, pointer_tool: PointerTool::Attractor, ..BoidParameters::default() };
+        let mut boid = Boid::with_parameters(400, 300, parameters);
+        boid.add_agent(Species::Prey, Point::new(200.0, 150.0), Point::new(0.0, 0.0));
+        boid.add_agent(Species::Prey, Point::new(30.0, 30.0), Point::new(0.0, 0.0));
+        boid.handle_pointer(&PointerEvent { kind: PointerKind::Down, position: pointer, button, shift, ctrl: false, alt: false });
+        boid.update(DT);
+        let pressed: Vec<Point> = boid.accelerations().collect();
+        // 離したら何も掛からない
+        boid.handle_pointer(&PointerEvent { kind: PointerKind::Up, position: pointer, button, shift, ctrl: false, alt: false });
+        boid.update(DT);
+        let released = boid.accelerations().next().unwrap();
+        assert!(released.x.abs() < 1e-6 && released.y.abs() < 1e-6, "{:?}", released);
+        pressed
+    };
+    let still = |a: Point| a.x.abs() < 1e-6 && a.y.abs() < 1e-6;
+
+    let attract = run(0, false);
+    assert!(attract[0].x > 0.0 && attract[0].y.abs() < 1e-6, "{:?}", attract);
+    assert!(still(attract[1]), "{:?}", attract);
+    // Shiftか右ボタンで斥力
+    for repel in [run(0, true), run(2, false)] {
+        assert!(repel[0].x < 0.0 && repel[0].y.abs() < 1e-6, "{:?}", repel);
+        assert!(still(repel[1]), "{:?}", repel);
+    }
+}
+
+#[test]
+fn bounce_keeps_agents_on_screen() {
+    let parameters = BoidParameters {
+        boid_count: 0,
+        boundary_mode: BoundaryMode::Bounce,
+        ..BoidParameters::default()
+    };
+    let mut boid = Boid::with_parameters(200, 100, parameters);
+    boid.update_boid_count(100);
+    for _ in 0..500 {
+        boid.update(DT);
+        assert!(boid.positions().all(|p| (0.0..=200.0).contains(&p.x) && (0.0..=100.0).contains(&p.y)));
+    }
+}
+
+#[test]
+fn camera_projects_the_target_to_the_screen_center() {
+    let camera = Camera::default();
+    let (center, scale) = camera.project(Vec3::ZERO, 800.0, 600.0).unwrap();
+    assert!((center.x - 400.0).abs() < 1e-9 && (center.y - 300.0).abs() < 1e-9);
+
+    // 手前のものほど大きく描く
+    for p in [Vec3::new(0.0, 0.0, 100.0), Vec3::new(0.0, 0.0, -100.0)] {
+        let (_, p_scale) = camera.project(p, 800.0, 600.0).unwrap();
+        let nearer = camera.to_view(p).z < camera.to_view(Vec3::ZERO).z;
+        assert_eq!(nearer, p_scale > scale);
+    }
+}
+
+#[test]
+fn boid3d_stays_in_the_box() {
+    let mut boid = Boid3d::new(800, 600, 7);
+    boid.update_boid_count(100);
+    for _ in 0..300 {
+        boid.update(DT);
+    }
+    assert!(boid.positions().all(|p| p.x.abs() <= 300.0 && p.y.abs() <= 300.0 && p.z.abs() <= 300.0));
+}
+
+#[test]
+fn lone_agent_moves_the_same_distance_at_any_frame_rate() {
+    // 風で加速し続けるので、加速度が時間で正しく積分されていないとフレームレートで位置がずれる
+    for integrator in [Integrator::Euler, Integrator::SemiImplicitEuler] {
+        let run = |wind_force: f64, dt: f64, steps: usize| {
+            let parameters = BoidParameters {
+                boid_count: 0,
+                seed: 3,
+                integrator,
+                wind_force,
+                wind_direction: 90.0,
+                ..BoidParameters::default()
+            };
+            let mut boid = Boid::with_parameters(4000, 4000, parameters);
+            boid.update_boid_count(1);
+            for _ in 0..steps {
+                boid.update(dt);
+            }
+            let position = boid.positions().next().unwrap();
+            position
+        };
+        let (a, b) = (run(0.05, 1.0 / 30.0, 30), run(0.05, 1.0 / 120.0, 120));
+        let still = run(0.0, 1.0 / 120.0, 120);
+        // 風で動いた距離に比べて、刻み幅の違いによるずれは十分小さい
+        let drift = (b.x - still.x).hypot(b.y - still.y);
+        assert!(drift > 20.0, "wind moved the agent only {}px", drift);
+        assert!((a.x - b.x).hypot(a.y - b.y) < drift * 0.05, "{:?} != {:?}", a, b);
+    }
+}
+
+fn measure(positions: &[Point], velocities: &[Point]) -> FlockMetrics {
+    let mut grid = SpatialGrid::new();
+    grid.rebuild(positions.iter().copied(), 1000.0, 1000.0, 50.0, false);
+    FlockMetrics::measure(positions, velocities, None, 50.0, &grid)
+}
+
+#[test]
+fn metrics_of_a_parallel_line_and_a_rotating_ring() {
+    // 同じ向きに並んだ2つのかたまり
+    let positions: Vec<Point> = (0..5).map(|i| Point::new(100.0 + i as f64 * 10.0, 100.0))
+        .chain((0..5).map(|i| Point::new(600.0 + i as f64 * 10.0, 600.0)))
+        .collect();
+    let velocities = vec![Point::new(3.0, 0.0); positions.len()];
+    let metrics = measure(&positions, &velocities);
+    assert!((metrics.polarization - 1.0).abs() < 1e-9);
+    assert!((metrics.mean_nearest_neighbor - 10.0).abs() < 1e-9);
+    assert_eq!(metrics.clusters, 2);
+
+    // 円周上を同じ向きに回っている群れ
+    let n = 36;
+    let (positions, velocities): (Vec<Point>, Vec<Point>) = (0..n)
+        .map(|i| {
+            let angle = i as f64 / n as f64 * std::f64::consts::TAU;
+            (
+                Point::new(500.0 + 200.0 * angle.cos(), 500.0 + 200.0 * angle.sin()),
+                Point::new(-angle.sin(), angle.cos()),
+            )
+        })
+        .unzip();
+    let metrics = measure(&positions, &velocities);
+    assert!(metrics.polarization < 1e-9);
+    assert!((metrics.milling - 1.0).abs() < 1e-9);
+    assert_eq!(metrics.clusters, 1);
+}
+
+#[test]
+fn clusters_join_below_one_pixel_cluster_distance() {
+    // 探す範囲は1px以上に広げるが、つなぐのはcluster_distanceより近いものだけ
+    let positions = vec![Point::new(100.0, 100.0), Point::new(100.3, 100.0), Point::new(100.9, 100.0)];
+    let velocities = vec![Point::new(1.0, 0.0); positions.len()];
+    let mut grid = SpatialGrid::new();
+    grid.rebuild(positions.iter().copied(), 1000.0, 1000.0, 0.5, false);
+    let metrics = FlockMetrics::measure(&positions, &velocities, None, 0.5, &grid);
+    assert_eq!(metrics.clusters, 2);
+}
+
+#[test]
+fn headless_run_records_every_interval() {
+    let parameters = BoidParameters {
+        boid_count: 20,
+        predator_count: 1,
+        seed: 7,
+        record_interval: 0.1,
+        ..BoidParameters::default()
+    };
+    let trajectory = run_headless(400, 300, parameters, 60, DT);
+
+    // 最初の状態と0.1秒ごと (1秒ぶん)
+    assert_eq!(trajectory.frames.len(), 11);
+    assert!(trajectory.frames.iter().all(|frame| frame.agents.len() == 21));
+    assert!((trajectory.frames[10].time - 1.0).abs() < 1e-9);
+
+    let csv = trajectory.to_csv();
+    assert!(csv.contains("# seed=7\n"));
+    assert_eq!(csv.lines().filter(|line| !line.starts_with('#')).count(), 1 + 11 * 21);
+    let json = trajectory.to_json();
+    assert!(json.starts_with("{\"width\":400,\"height\":300,\"seed\":7,"));
+    assert_eq!(json.matches("\"species\":\"predator\"").count(), 11);
+}
+
+#[test]
+fn parameter_changes_are_applied_through_the_registry() {
+    let mut boid = Boid::new(400, 300, 3);
+    let parameters = boid.parameters();
+    let values = boid.parameter_values();
+    assert_eq!(parameters.len(), values.len());
+    for (spec, value) in parameters.iter().zip(&values) {
+        assert_eq!(parameters.iter().filter(|other| other.name == spec.name).count(), 1);
+        assert_eq!(spec.value_from(value.as_f64()), *value, "{} is out of its range", spec.name);
+    }
+
+    let index = parameters.iter().position(|spec| spec.name == "boidcount").unwrap();
+    let value = parameters[index].value_from(25.4);
+    assert_eq!(value, ParameterValue::Integer(25));
+    boid.handle_parameter(&ParameterChange { index, value });
+    assert_eq!(boid.positions().count(), 25);
+    assert_eq!(boid.parameter_values()[index], value);
+}
+
+#[test]
+fn presets_only_name_known_parameters_and_keep_the_seed() {
+    let boid = Boid::new(400, 300, 3);
+    let parameters = boid.parameters();
+    for preset in boid.presets() {
+        let values = decode_query(&preset.query(&parameters, &boid.parameter_defaults()));
+        assert_eq!(values.len(), parameters.len() - 1, "{}", preset.name);
+        assert!(values.iter().all(|(name, _)| name != "seed"));
+        for (name, _) in preset.values {
+            assert!(parameters.iter().any(|spec| spec.name == *name), "{} has unknown {}", preset.name, name);
+        }
+    }
+    assert_eq!(decode_query("?seed=12&rule=23/3&boidcount=abc&alignforce=0.5"), vec![("seed".to_string(), 12.0), ("alignforce".to_string(), 0.5)]);
+}
+
+#[test]
+fn default_preset_matches_the_initial_values() {
+    // プリセットに書いていないパラメータはゲームの既定値になる
+    let check = |game: &dyn Game| {
+        let fresh: Vec<(String, f64)> = game
+            .parameters()
+            .iter()
+            .zip(game.parameter_values())
+            .filter(|(spec, _)| spec.name != "seed")
+            .map(|(spec, value)| (spec.name.to_string(), value.as_f64()))
+            .collect();
+        let preset = game.presets().into_iter().find(|preset| preset.name == "Default").unwrap();
+        assert_eq!(decode_query(&preset.query(&game.parameters(), &game.parameter_defaults())), fresh);
+    };
+    check(&Boid::with_parameters(400, 300, BoidParameters::default()));
+    check(&Boid3d::new(400, 300, 0));
+
+    // 既定値のシードは今のシードのまま
+    let seed = |game: &dyn Game| {
+        let index = game.parameters().iter().position(|spec| spec.name == "seed").unwrap();
+        game.parameter_defaults()[index].as_f64()
+    };
+    assert_eq!(seed(&Boid::with_parameters(400, 300, BoidParameters { seed: 9, ..BoidParameters::default() })), 9.0);
+    assert_eq!(seed(&Boid3d::new(400, 300, 9)), 9.0);
+}
+
+#[test]
+fn wind_carries_a_lone_agent_downwind() {
+    let parameters = BoidParameters { boid_count: 0, seed: 5, wind_force: 0.2, wind_direction: 90.0, ..BoidParameters::default() };
+    let mut boid = Boid::with_parameters(4000, 4000, parameters);
+    boid.update_boid_count(1);
+    for _ in 0..60 {
+        boid.update(DT);
+    }
+    let start = boid.positions().next().unwrap();
+    for _ in 0..60 {
+        boid.update(DT);
+    }
+    let end = boid.positions().next().unwrap();
+    // 1秒で最高速 (180px/s) に達して、あとは真下に流される
+    assert!(end.y - start.y > 170.0 && (end.x - start.x).abs() < 10.0, "{:?} -> {:?}", start, end);
+}
+
+#[test]
+fn flock_visits_waypoints_in_order() {
+    let parameters = BoidParameters {
+        boid_count: 0,
+        seed: 9,
+        boundary_mode: BoundaryMode::Bounce,
+        goal_force: 0.3,
+        ..BoidParameters::default()
+    };
+    let mut boid = Boid::with_parameters(1000, 1000, parameters);
+    boid.update_boid_count(1);
+    let waypoints = [Point::new(800.0, 200.0), Point::new(200.0, 800.0)];
+    for waypoint in waypoints {
+        boid.add_waypoint(waypoint);
+    }
+    let mut visits = Vec::new();
+    for _ in 0..60 * 20 {
+        boid.update(DT);
+        let position = boid.positions().next().unwrap();
+        if let Some(i) = waypoints.iter().position(|w| (w.x - position.x).powi(2) + (w.y - position.y).powi(2) < 40f64.powi(2)) {
+            if visits.last() != Some(&i) {
+                visits.push(i);
+            }
+        }
+    }
+    assert!(visits.len() >= 3 && visits.iter().enumerate().all(|(k, i)| *i == k % 2), "{:?}", visits);
+}
+
+#[test]
+fn resize_rescales_agents_and_waypoints_to_the_new_size() {
+    let mut boid = Boid::with_parameters(400, 300, BoidParameters { boid_count: 0, seed: 4, ..BoidParameters::default() });
+    boid.update_boid_count(50);
+    boid.add_waypoint(Point::new(200.0, 150.0));
+    let before: Vec<Point> = boid.positions().collect();
+
+    boid.on_resize(800, 150);
+    for (a, b) in before.iter().zip(boid.positions()) {
+        assert!((b.x - a.x * 2.0).abs() < 1e-3 && (b.y - a.y * 0.5).abs() < 1e-3, "{:?} -> {:?}", a, b);
+    }
+    assert_eq!(boid.waypoints(), &[Point::new(400.0, 75.0)]);
+    for _ in 0..60 {
+        boid.update(DT);
+    }
+    assert!(boid.positions().all(|p| (0.0..=800.0).contains(&p.x) && (0.0..=150.0).contains(&p.y)));
+}
+
+#[test]
+fn caught_prey_vanish_or_respawn() {
+    // catch_distanceを画面より大きくして、最初のステップで全部の被食者が捕まるようにする
+    let run = |respawn_prey: bool| {
+        let parameters = BoidParameters {
+            boid_count: 0,
+            predator_count: 0,
+            catch_distance: 1000.0,
+            respawn_prey,
+            seed: 5,
+            ..BoidParameters::default()
+        };
+        let mut boid = Boid::with_parameters(200, 200, parameters);
+        boid.update_boid_count(30);
+        boid.update_predator_count(1);
+        assert_eq!(boid.positions().count(), 31);
+        let before: Vec<Point> = boid.positions().collect();
+        boid.update(DT);
+        (before, boid.positions().collect::<Vec<Point>>())
+    };
+
+    let (_, after) = run(false);
+    assert_eq!(after.len(), 1);
+
+    // 出し直した被食者は数が戻り、動いただけでは届かない位置に移っているものがある
+    let (before, after) = run(true);
+    assert_eq!(after.len(), 31);
+    let max_step = BoidParameters::default().max_speed * DT * 2.0;
+    assert!(before.iter().zip(&after).any(|(a, b)| (a.x - b.x).hypot(a.y - b.y) > max_step));
+}
+
+#[test]
+fn tracks_keep_their_ids_when_prey_are_caught() {
+    // 2番目の被食者だけが捕食者のすぐ横にいて、最初のステップで捕まる
+    let parameters = BoidParameters {
+        boid_count: 0,
+        predator_count: 0,
+        respawn_prey: false,
+        record_interval: DT,
+        seed: 6,
+        ..BoidParameters::default()
+    };
+    let mut boid = Boid::with_parameters(400, 300, parameters);
+    boid.add_agent(Species::Prey, Point::new(50.0, 50.0), Point::new(10.0, 0.0));
+    boid.add_agent(Species::Prey, Point::new(201.0, 150.0), Point::new(10.0, 0.0));
+    boid.add_agent(Species::Prey, Point::new(350.0, 250.0), Point::new(-10.0, 0.0));
+    boid.add_agent(Species::Predator, Point::new(200.0, 150.0), Point::new(10.0, 0.0));
+    boid.start_recording();
+    for _ in 0..3 {
+        boid.update(DT);
+    }
+    assert_eq!(boid.ids().collect::<Vec<usize>>(), vec![0, 2, 3]);
+
+    let trajectory = boid.take_trajectory();
+    assert_eq!(trajectory.frames.len(), 4);
+    let ids = |f: usize| trajectory.frames[f].agents.iter().map(|agent| agent.id).collect::<Vec<usize>>();
+    assert_eq!(ids(0), vec![0, 1, 2, 3]);
+    assert_eq!(ids(3), vec![0, 2, 3]);
+    // 残ったものは同じidのまま、1ステップで動ける距離しか離れていない
+    let max_step = BoidParameters::default().predator_max_speed * DT * 2.0;
+    for pair in trajectory.frames.windows(2) {
+        for agent in &pair[1].agents {
+            let previous = pair[0].agents.iter().find(|other| other.id == agent.id).unwrap();
+            assert_eq!(previous.species, agent.species);
+            let (a, b) = (previous.position, agent.position);
+            assert!((a.x - b.x).hypot(a.y - b.y) < max_step, "agent {} jumps from {:?} to {:?}", agent.id, a, b);
+        }
+    }
+    // CSVのagentの列もid
+    let csv = trajectory.to_csv();
+    let last: Vec<&str> = csv.lines().rev().take(3).map(|line| line.split(',').nth(1).unwrap()).collect();
+    assert_eq!(last, vec!["3", "2", "0"]);
+    assert_eq!(trajectory.to_json().matches("\"id\":1,").count(), 1);
+}
+
+#[test]
+fn agents_at_max_speed_do_not_tunnel_through_thin_obstacles() {
+    // 強い風で右向きに最高速まで加速させ、1ステップの移動 (30px) を障害物の厚みより大きくする
+    // 1ステップで動いた線分の上に障害物の中の点があれば、すり抜けたことになる
+    let obstacles = [
+        Obstacle::Polygon {
+            vertices: vec![Point::new(300.0, 20.0), Point::new(301.0, 20.0), Point::new(301.0, 80.0), Point::new(300.0, 80.0)],
+        },
+        Obstacle::Circle { center: Point::new(300.0, 50.0), radius: 5.0 },
+    ];
+    for obstacle in obstacles {
+        let parameters = BoidParameters {
+            boid_count: 0,
+            max_speed: 900.0,
+            wind_force: 1.0,
+            boundary_mode: BoundaryMode::Bounce,
+            seed: 6,
+            ..BoidParameters::default()
+        };
+        let mut boid = Boid::with_parameters(400, 100, parameters);
+        boid.update_boid_count(50);
+        boid.add_obstacle(obstacle.clone());
+        let mut hits = 0;
+        for _ in 0..120 {
+            let before: Vec<Point> = boid.positions().collect();
+            boid.update(1.0 / 30.0);
+            for (a, b) in before.iter().zip(boid.positions()) {
+                for k in 0..=100 {
+                    let t = k as f64 / 100.0;
+                    let p = Point::new(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t);
+                    assert!(!obstacle.contains(p), "{:?} -> {:?} passes through {:?}", a, b, obstacle);
+                }
+                if obstacle.surface(b).0 < 1.0 {
+                    hits += 1;
+                }
+            }
+        }
+        // 実際に障害物まで届いたものがある
+        assert!(hits > 0);
+    }
+}
+
+#[test]
+fn hues_and_heat_map_to_primary_colors() {
+    assert_eq!(hsv_to_rgb(0.0, 1.0, 1.0), (255, 0, 0));
+    assert_eq!(hsv_to_rgb(120.0, 1.0, 1.0), (0, 255, 0));
+    assert_eq!(hsv_to_rgb(240.0, 1.0, 1.0), (0, 0, 255));
+    assert_eq!(hsv_to_rgb(120.0, 0.0, 1.0), (255, 255, 255));
+    // 範囲外は端の色
+    assert_eq!(heat_rgb(0.0), (0, 0, 255));
+    assert_eq!(heat_rgb(1.0), (255, 0, 0));
+    assert_eq!(heat_rgb(-3.0), heat_rgb(0.0));
+    assert_eq!(heat_rgb(7.0), heat_rgb(1.0));
+}
+
+#[test]
+fn trails_keep_only_the_latest_positions() {
+    let parameters = BoidParameters { boid_count: 0, trails: true, trail_length: 5, seed: 8, ..BoidParameters::default() };
+    let mut boid = Boid::with_parameters(400, 300, parameters);
+    boid.update_boid_count(10);
+    for step in 1..=12 {
+        boid.update(DT);
+        assert!(boid.trails().all(|trail| trail.len() == step.min(5)));
+    }
+    // 一番新しいものが今の位置
+    for (trail, position) in boid.trails().zip(boid.positions()) {
+        assert_eq!(trail.back(), Some(&position));
+    }
+}
@@ -0,0 +1,68 @@
+use rust_webpack_template::export::{encode_gif, encode_png, LifeExport, Palette, Region};
+use rust_webpack_template::hashlife::{Pattern, Rule, Universe};
+
+fn blinker() -> Universe {
+    let mut universe = Universe::new();
+    universe.initilaize();
+    universe.set_pattern((-1, 0), &Pattern::from_rows(&["OOO"]).unwrap());
+    universe
+}
+
+#[test]
+fn png_has_region_size_and_cell_colors() {
+    let universe = blinker();
+    let region = Region::new((-2, -2), (3, 3), 4).unwrap();
+    let bytes = encode_png(&universe, &region, &Palette::for_rule(Rule::CONWAY)).unwrap();
+
+    let mut reader = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!((info.width, info.height), (20, 20));
+    assert_eq!(info.color_type, png::ColorType::Indexed);
+
+    // (-1, 0)のセルは生、(0, -1)のセルは死
+    assert_eq!(pixels[(2 * 4) * 20 + 4], 1);
+    assert_eq!(pixels[4 * 20 + 2 * 4], 0);
+}
+
+#[test]
+fn gif_has_one_frame_per_generation() {
+    let mut universe = blinker();
+    let region = Region::new((-2, -2), (3, 3), 2).unwrap();
+    let bytes = encode_gif(&mut universe, &region, &Palette::for_rule(Rule::CONWAY), 4, 100).unwrap();
+
+    let mut decoder = gif::DecodeOptions::new();
+    decoder.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = decoder.read_info(bytes.as_slice()).unwrap();
+    let mut frames = 0;
+    while let Some(frame) = decoder.read_next_frame().unwrap() {
+        assert_eq!((frame.width, frame.height), (10, 10));
+        assert_eq!(frame.delay, 10);
+        frames += 1;
+    }
+    assert_eq!(frames, 5);
+    assert_eq!(universe.generation(), 4);
+}
+
+#[test]
+fn region_rejects_empty_rectangles() {
+    assert!(Region::new((0, 0), (0, 5), 1).is_err());
+    assert!(Region::new((0, 0), (5, 5), 0).is_err());
+}
+
+#[test]
+fn region_rejects_images_with_too_many_pixels() {
+    // 幅と高さはそれぞれ上限内でも、全体が大きすぎるものは作らない
+    assert!(Region::new((0, 0), (65535, 65535), 1).is_err());
+    assert!(Region::new((0, 0), (8192, 8192), 1).is_ok());
+    assert!(Region::new((0, 0), (8192, 8193), 1).is_err());
+    assert!(Region::new((0, 0), (4096, 4096), 2).is_ok());
+}
+
+#[test]
+fn regions_near_the_i32_limit_do_not_overflow() {
+    let export = LifeExport::new("23/3", "OOO").unwrap();
+    let bytes = export.png(i32::MAX - 1, i32::MAX - 1, 4, 4, 2).unwrap();
+    let reader = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+    assert_eq!((reader.info().width, reader.info().height), (8, 8));
+}
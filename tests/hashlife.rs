@@ -0,0 +1,216 @@
+use rust_webpack_template::hashlife::{Cell, CellDiff, Orientation, Pattern, PatternMatch, Position, Rule, Universe};
+
+fn universe() -> Universe {
+    universe_with(Rule::CONWAY)
+}
+
+fn universe_with(rule: Rule) -> Universe {
+    let mut universe = Universe::with_rule(rule);
+    universe.initilaize();
+    universe
+}
+
+fn live_positions(universe: &Universe) -> Vec<Position> {
+    let mut positions: Vec<Position> = universe.cells_in((-64, -64), (64, 64)).into_iter().map(|(pos, _)| pos).collect();
+    positions.sort_by_key(|pos| (pos.y, pos.x));
+    positions
+}
+
+#[test]
+fn cells_on_and_just_outside_the_root_edge_keep_their_positions() {
+    // 最初のルート (レベル3) は -4..=3 を覆う すぐ外側の-5と4に置いたら広げて置く
+    let row = [-5, -4, 3, 4];
+    let mut universe = universe();
+    for x in row {
+        universe.set_cell((x, 0), Cell::ALIVE);
+    }
+    for x in row {
+        assert_eq!(universe.get_cell((x, 0)), Cell::ALIVE, "x = {}", x);
+    }
+    assert_eq!(universe.population(), 4);
+    assert_eq!(live_positions(&universe), row.map(|x| Position::new(x, 0)).to_vec());
+}
+
+#[test]
+fn lettered_and_plain_rules_parse_to_the_same_rule() {
+    let lettered: Rule = "B3/S23".parse().unwrap();
+    let plain: Rule = "23/3".parse().unwrap();
+    assert_eq!(lettered, plain);
+    assert_eq!(plain, Rule::CONWAY);
+    assert_eq!("B2/S/C3".parse::<Rule>().unwrap(), Rule::BRIANS_BRAIN);
+    assert!("23".parse::<Rule>().is_err());
+    assert!("23/3/1".parse::<Rule>().is_err());
+    assert!("29/3".parse::<Rule>().is_err());
+}
+
+#[test]
+fn generations_rules_round_trip_through_display() {
+    for (text, rule) in [("/2/3", Rule::BRIANS_BRAIN), ("345/2/4", Rule::STAR_WARS)] {
+        let parsed: Rule = text.parse().unwrap();
+        assert_eq!(parsed, rule);
+        assert_eq!(parsed.to_string(), text);
+        assert_eq!(parsed.to_string().parse::<Rule>().unwrap(), parsed);
+    }
+}
+
+#[test]
+fn brians_brain_cells_go_alive_dying_dead() {
+    let rule = Rule::BRIANS_BRAIN;
+    assert_eq!(rule.next(Cell::DEAD, 2), Cell::ALIVE);
+    assert_eq!(rule.next(Cell::ALIVE, 2), Cell(2));
+    // 不応期のセルは近傍に関係なく死ぬ (生まれもしない)
+    assert_eq!(rule.next(Cell(2), 2), Cell::DEAD);
+    assert_eq!(rule.next(Cell::DEAD, 3), Cell::DEAD);
+}
+
+#[test]
+fn brians_brain_pair_spawns_above_and_below() {
+    let mut universe = universe_with(Rule::BRIANS_BRAIN);
+    universe.set_cell((0, 0), Cell::ALIVE);
+    universe.set_cell((1, 0), Cell::ALIVE);
+
+    universe.evolve();
+    for pos in [(0, -1), (1, -1), (0, 1), (1, 1)] {
+        assert_eq!(universe.get_cell(pos), Cell::ALIVE, "{:?}", pos);
+    }
+    assert_eq!(universe.get_cell((0, 0)), Cell(2));
+    assert_eq!(universe.get_cell((1, 0)), Cell(2));
+    assert_eq!(universe.population(), 6);
+
+    // 次の世代で不応期のセルは消える
+    universe.evolve();
+    assert_eq!(universe.get_cell((0, 0)), Cell::DEAD);
+    assert_eq!(universe.get_cell((1, 0)), Cell::DEAD);
+    assert_eq!(universe.get_cell((0, -1)), Cell(2));
+}
+
+fn glider() -> Pattern {
+    Pattern::from_rows(&[".O.", "..O", "OOO"]).unwrap()
+}
+
+fn with_pattern(top_left: (i64, i64), pattern: &Pattern) -> Universe {
+    let mut universe = universe();
+    universe.set_pattern(top_left, pattern);
+    universe
+}
+
+#[test]
+fn same_cells_hash_equal_across_universes_and_root_sizes() {
+    let a = with_pattern((0, 0), &glider());
+    let mut b = with_pattern((0, 0), &glider());
+    assert_eq!(a.content_hash(), b.content_hash());
+    assert_eq!(a.node_hash(a.root().unwrap()), b.node_hash(b.root().unwrap()));
+
+    // 遠くにセルを置いて消すとルートは大きくなるが、内容は同じ
+    b.set_cell((200, -200), Cell::ALIVE);
+    b.set_cell((200, -200), Cell::DEAD);
+    assert_ne!(a.node_hash(a.root().unwrap()), b.node_hash(b.root().unwrap()));
+    assert_eq!(a.content_hash(), b.content_hash());
+
+    // 同じだけ広げればルートのハッシュも一致する (Idはuniverseごとに違う)
+    let mut a = a;
+    a.set_cell((200, -200), Cell::ALIVE);
+    a.set_cell((200, -200), Cell::DEAD);
+    assert_eq!(a.node_hash(a.root().unwrap()), b.node_hash(b.root().unwrap()));
+}
+
+#[test]
+fn shifted_or_different_cells_hash_differently() {
+    let base = with_pattern((0, 0), &glider()).content_hash();
+    assert_ne!(with_pattern((1, 0), &glider()).content_hash(), base);
+    assert_ne!(with_pattern((0, 0), &Pattern::from_rows(&["OOO"]).unwrap()).content_hash(), base);
+    assert_ne!(universe().content_hash(), base);
+    // 状態の違い (不応期) も区別する
+    let dying = with_pattern((0, 0), &Pattern::from_rows(&[".O.", "..O", "OO2"]).unwrap());
+    assert_ne!(dying.content_hash(), base);
+}
+
+fn match_positions(matches: &[PatternMatch]) -> Vec<(i64, i64)> {
+    matches.iter().map(|m| (m.position.x, m.position.y)).collect()
+}
+
+#[test]
+fn glider_is_found_in_every_orientation() {
+    for orientation in Orientation::all() {
+        let oriented = glider().oriented(orientation);
+        let universe = with_pattern((5, -7), &oriented);
+        let matches = universe.find_pattern(&glider());
+        assert_eq!(match_positions(&matches), vec![(5, -7)], "{:?}", orientation);
+        // 対称なので報告される向きは違うことがあるが、形は同じ
+        assert_eq!(glider().oriented(matches[0].orientation), oriented, "{:?}", orientation);
+    }
+}
+
+#[test]
+fn matches_straddling_the_origin_and_quadrant_edges_are_found() {
+    let mut universe = universe();
+    // 原点 (ルートの中央) をまたぐ
+    universe.set_pattern((-1, -1), &glider());
+    // 原点から離れたところで、下の方のレベルの境界 (x = 16) をまたぐ
+    universe.set_pattern((15, 20), &glider());
+    assert_eq!(match_positions(&universe.find_pattern(&glider())), vec![(-1, -1), (15, 20)]);
+}
+
+#[test]
+fn identical_subtrees_give_one_match_each() {
+    // 32ずつずらすと同じ部分木 (同じId) に入る
+    let mut universe = universe();
+    for top_left in [(0, 0), (32, 0), (0, 32), (32, 32)] {
+        universe.set_pattern(top_left, &glider());
+    }
+    assert_eq!(match_positions(&universe.find_pattern(&glider())), vec![(0, 0), (32, 0), (0, 32), (32, 32)]);
+}
+
+#[test]
+fn near_misses_are_not_matches() {
+    assert!(with_pattern((0, 0), &Pattern::from_rows(&["OOO"]).unwrap()).find_pattern(&glider()).is_empty());
+    // 外接矩形の中に余計なセルがある
+    let crowded = with_pattern((0, 0), &Pattern::from_rows(&["OO.", "..O", "OOO"]).unwrap());
+    assert!(crowded.find_pattern(&glider()).is_empty());
+    assert!(universe().find_pattern(&glider()).is_empty());
+}
+
+type Cells = Vec<(i64, i64)>;
+
+// (生まれたセル, 死んだセル) を座標順に
+fn sorted(diff: CellDiff) -> (Cells, Cells) {
+    let key = |positions: Vec<Position>| {
+        let mut positions: Cells = positions.into_iter().map(|pos| (pos.x, pos.y)).collect();
+        positions.sort();
+        positions
+    };
+    (key(diff.born), key(diff.died))
+}
+
+#[test]
+fn blinker_step_diff_lists_births_and_deaths() {
+    let mut universe = with_pattern((0, 0), &Pattern::from_rows(&["OOO"]).unwrap());
+    universe.evolve();
+    assert_eq!(sorted(universe.last_step_diff((-16, -16), (16, 16))), (vec![(1, -1), (1, 1)], vec![(0, 0), (2, 0)]));
+}
+
+#[test]
+fn diff_handles_roots_of_different_levels() {
+    let mut universe = with_pattern((0, 0), &Pattern::from_rows(&["OOO"]).unwrap());
+    let small = universe.root().unwrap();
+    // 遠くにセルを置くとルートが広がる
+    universe.set_cell((40, -40), Cell::ALIVE);
+    let large = universe.root().unwrap();
+
+    let region = ((-64, -64), (64, 64));
+    assert_eq!(sorted(universe.diff(small, large, region.0, region.1)), (vec![(40, -40)], vec![]));
+    assert_eq!(sorted(universe.diff(large, small, region.0, region.1)), (vec![], vec![(40, -40)]));
+    assert_eq!(sorted(universe.diff(small, small, region.0, region.1)), (vec![], vec![]));
+}
+
+#[test]
+fn diff_only_reports_cells_inside_a_partly_overlapping_region() {
+    let mut universe = with_pattern((0, 0), &Pattern::from_rows(&["OOO"]).unwrap());
+    universe.evolve();
+    // 木よりずっと広いが、x = 1 より右は含まない領域
+    assert_eq!(sorted(universe.last_step_diff((-1000, -1000), (1, 1000))), (vec![], vec![(0, 0)]));
+    // 上半分だけ
+    assert_eq!(sorted(universe.last_step_diff((-1000, -1000), (1000, 0))), (vec![(1, -1)], vec![]));
+    // 木の外だけ
+    assert_eq!(sorted(universe.last_step_diff((500, 500), (600, 600))), (vec![], vec![]));
+}